@@ -0,0 +1,232 @@
+use std::{env, fs, path::Path};
+
+use serde_json::Value;
+
+//Generates the typed requests of src/requests.rs and the Event enum of src/event.rs from protocol/protocol.json.
+//protocol.json has the format of the file published by obs-websocket (docs/generated/protocol.json),
+//update it to pick up new requests and events.
+fn main() {
+    let protocol_path = Path::new("protocol").join("protocol.json");
+    println!("cargo:rerun-if-changed={}", protocol_path.display());
+
+    let protocol = fs::read_to_string(&protocol_path).expect("could not read protocol/protocol.json");
+    let protocol: Value = serde_json::from_str(&protocol).expect("protocol/protocol.json is not valid JSON");
+
+    let mut requests = String::new();
+    for request in protocol["requests"].as_array().into_iter().flatten() {
+        requests.push_str(&request_code(request));
+    }
+    let events = events_code(protocol["events"].as_array().map(Vec::as_slice).unwrap_or_default());
+
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR not set");
+    fs::write(Path::new(&out_dir).join("requests.rs"), requests).expect("could not write generated requests");
+    fs::write(Path::new(&out_dir).join("events.rs"), events).expect("could not write generated events");
+}
+
+struct Field {
+    name: String,
+    rust_name: String,
+    rust_type: String,
+    optional: bool,
+    description: String
+}
+
+fn request_code(request: &Value) -> String {
+    let request_type = request["requestType"].as_str().expect("request without requestType");
+    let request_fields = fields(&request["requestFields"]);
+    let response_fields = fields(&request["responseFields"]);
+
+    let mut code = String::new();
+    code.push_str(&doc_comment(request["description"].as_str().unwrap_or_default(), ""));
+    code.push_str("#[derive(Clone, Debug, Default, PartialEq, Serialize)]\n");
+    code.push_str(&format!("pub struct {request_type} {{\n"));
+    for field in &request_fields {
+        code.push_str(&doc_comment(&field.description, "    "));
+        if field.optional {
+            code.push_str(&format!("    #[serde(rename = \"{}\", skip_serializing_if = \"Option::is_none\")]\n", field.name));
+            code.push_str(&format!("    pub {}: Option<{}>,\n", field.rust_name, field.rust_type));
+        }
+        else {
+            code.push_str(&format!("    #[serde(rename = \"{}\")]\n", field.name));
+            code.push_str(&format!("    pub {}: {},\n", field.rust_name, field.rust_type));
+        }
+    }
+    code.push_str("}\n\n");
+
+    let response_type = if response_fields.is_empty() {
+        "()".to_string()
+    }
+    else {
+        format!("{request_type}Response")
+    };
+
+    code.push_str(&format!("impl Request for {request_type} {{\n"));
+    code.push_str(&format!("    const REQUEST_TYPE: &'static str = \"{request_type}\";\n"));
+    code.push_str(&format!("    type Response = {response_type};\n"));
+    code.push_str("}\n\n");
+
+    if !response_fields.is_empty() {
+        code.push_str(&format!("///Response data of {request_type}\n"));
+        code.push_str("#[derive(Clone, Debug, Default, PartialEq, Deserialize)]\n");
+        code.push_str(&format!("pub struct {response_type} {{\n"));
+        for field in &response_fields {
+            code.push_str(&doc_comment(&field.description, "    "));
+            code.push_str(&format!("    #[serde(rename = \"{}\", default, deserialize_with = \"null_as_default\")]\n", field.name));
+            code.push_str(&format!("    pub {}: {},\n", field.rust_name, field.rust_type));
+        }
+        code.push_str("}\n\n");
+    }
+
+    code
+}
+
+fn events_code(events: &[Value]) -> String {
+    let mut code = String::new();
+    code.push_str("///Event sent by OBS (opcode 5), see Event::from_payload\n");
+    code.push_str("#[derive(Clone, Debug, PartialEq, Deserialize)]\n");
+    code.push_str("#[serde(tag = \"eventType\", content = \"eventData\")]\n");
+    code.push_str("pub enum Event {\n");
+    for event in events {
+        let event_type = event["eventType"].as_str().expect("event without eventType");
+        let data_fields = fields(&event["dataFields"]);
+        code.push_str(&doc_comment(event["description"].as_str().unwrap_or_default(), "    "));
+        if data_fields.is_empty() {
+            code.push_str(&format!("    {event_type},\n"));
+            continue;
+        }
+        //the data of BroadcastCustomEvent is the eventData object itself, not a field eventData within it
+        if event_type == "CustomEvent" {
+            code.push_str("    CustomEvent {\n");
+            code.push_str("        ///eventData of the BroadcastCustomEvent request\n");
+            code.push_str("        #[serde(flatten)]\n");
+            code.push_str("        event_data: Value\n");
+            code.push_str("    },\n");
+            continue;
+        }
+        code.push_str(&format!("    {event_type} {{\n"));
+        for field in &data_fields {
+            code.push_str(&doc_comment(&field.description, "        "));
+            code.push_str(&format!("        #[serde(rename = \"{}\", default, deserialize_with = \"null_as_default\")]\n", field.name));
+            code.push_str(&format!("        {}: {},\n", field.rust_name, field.rust_type));
+        }
+        code.push_str("    },\n");
+    }
+    code.push_str("    ///Event this version does not know, or whose data does not match the protocol\n");
+    code.push_str("    #[serde(skip)]\n");
+    code.push_str("    Unknown {\n");
+    code.push_str("        event_type: String,\n");
+    code.push_str("        event_intent: OBSEventSubscription,\n");
+    code.push_str("        event_data: Value\n");
+    code.push_str("    }\n");
+    code.push_str("}\n\n");
+
+    code.push_str("///Event types of protocol/protocol.json\n");
+    code.push_str(&format!("const EVENT_TYPES: [&str; {}] = [\n", events.len()));
+    for event in events {
+        code.push_str(&format!("    \"{}\",\n", event["eventType"].as_str().unwrap_or_default()));
+    }
+    code.push_str("];\n\n");
+
+    code.push_str("impl Event {\n");
+    code.push_str("    ///Name of the event, e.g. \"CurrentProgramSceneChanged\"\n");
+    code.push_str("    pub fn event_type(&self) -> &str {\n");
+    code.push_str("        match self {\n");
+    for event in events {
+        let event_type = event["eventType"].as_str().unwrap_or_default();
+        code.push_str(&format!("            {} => \"{event_type}\",\n", event_pattern(event)));
+    }
+    code.push_str("            Event::Unknown { event_type, .. } => event_type\n");
+    code.push_str("        }\n");
+    code.push_str("    }\n\n");
+    code.push_str("    ///Subscription category the event belongs to\n");
+    code.push_str("    pub fn event_intent(&self) -> OBSEventSubscription {\n");
+    code.push_str("        match self {\n");
+    for event in events {
+        let subscription = event["eventSubscription"].as_str().unwrap_or("None");
+        code.push_str(&format!("            {} => OBSEventSubscription::{subscription},\n", event_pattern(event)));
+    }
+    code.push_str("            Event::Unknown { event_intent, .. } => *event_intent\n");
+    code.push_str("        }\n");
+    code.push_str("    }\n");
+    code.push_str("}\n");
+
+    code
+}
+
+fn event_pattern(event: &Value) -> String {
+    let event_type = event["eventType"].as_str().unwrap_or_default();
+    if fields(&event["dataFields"]).is_empty() {
+        format!("Event::{event_type}")
+    }
+    else {
+        format!("Event::{event_type} {{ .. }}")
+    }
+}
+
+fn fields(fields: &Value) -> Vec<Field> {
+    fields.as_array().into_iter().flatten()
+        .filter_map(|field| {
+            let name = field["valueName"].as_str()?;
+            //sub fields of objects ("keyModifiers.shift") are part of the object field
+            if name.contains('.') {
+                return None;
+            }
+            let value_type = field["valueType"].as_str().unwrap_or("Any");
+            Some(Field {
+                name: name.to_string(),
+                rust_name: snake_case(name),
+                rust_type: rust_type(name, value_type),
+                optional: field["valueOptional"].as_bool().unwrap_or(false),
+                description: field["valueDescription"].as_str().unwrap_or_default().to_string()
+            })
+        })
+        .collect()
+}
+
+//protocol.json only knows "Number" for integers and floats alike. These fields count things, are ids, indices,
+//sizes in pixels or bytes, or times in ms or frames, the values OBS sends for them are always integers.
+//Every other number is an f64, which also parses integers, so a new field is never rejected.
+const INTEGER_FIELDS: [&str; 31] = [
+    "rpcVersion", "renderSkippedFrames", "renderTotalFrames", "outputSkippedFrames", "outputTotalFrames",
+    "webSocketSessionIncomingMessages", "webSocketSessionOutgoingMessages", "sleepMillis", "sleepFrames",
+    "fpsNumerator", "fpsDenominator", "baseWidth", "baseHeight", "outputWidth", "outputHeight",
+    "imageWidth", "imageHeight", "imageCompressionQuality", "transitionDuration", "sceneItemId",
+    "inputAudioSyncOffset", "filterIndex", "searchOffset", "sceneItemIndex", "outputDuration", "outputBytes",
+    "mediaDuration", "mediaCursor", "mediaCursorOffset", "monitorIndex", "inputKindCaps"
+];
+
+fn rust_type(name: &str, value_type: &str) -> String {
+    match value_type {
+        "String" => "String".to_string(),
+        "Boolean" => "bool".to_string(),
+        "Number" if INTEGER_FIELDS.contains(&name) => "i64".to_string(),
+        "Number" => "f64".to_string(),
+        _ => match value_type.strip_prefix("Array<").and_then(|inner| inner.strip_suffix('>')) {
+            Some(inner) => format!("Vec<{}>", rust_type(name, inner)),
+            None => "Value".to_string()
+        }
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for character in name.chars() {
+        if character.is_ascii_uppercase() {
+            if !snake.is_empty() {
+                snake.push('_');
+            }
+            snake.push(character.to_ascii_lowercase());
+        }
+        else {
+            snake.push(character);
+        }
+    }
+    snake
+}
+
+fn doc_comment(description: &str, indent: &str) -> String {
+    description.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| format!("{indent}///{}\n", line.trim()))
+        .collect()
+}
@@ -0,0 +1,525 @@
+use std::{collections::HashMap, future::Future, io::{self, ErrorKind}, net::SocketAddr, pin::Pin, sync::{Arc, Mutex}, task::{Context, Poll}, time::Duration};
+
+use futures_core::Stream;
+use log::{debug, info, warn};
+use serde_json::Value;
+use tokio::{io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf}, net::TcpStream, runtime::Handle, sync::{mpsc, oneshot, watch}, task::JoinHandle, time::timeout};
+
+use crate::{lock, batch::RequestBatch, close_frame::{CloseFrame, NORMAL_CLOSURE}, codec::{self, Assembler, Control, Violation}, connect_options::ConnectOptions, deflate::{Deflate, DeflateParameters}, encoding::{self, Encoding}, error::Error, event::Event, handshake, header::Header, message::Message, obs::{OBSEventSubscription, OBSOpcode}, payload::{Payload, Route}, requests::Request, url::WebsocketUrl, websocket::{State, WebsocketError}};
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+///Events not taken from an EventStream are dropped (newest first) when its buffer is full
+const EVENT_STREAM_CAPACITY: usize = 1024;
+
+//TCP or TLS connection under the WebSocket
+trait Transport: AsyncRead + AsyncWrite + Send + Sync + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Send + Sync + Unpin> Transport for T {}
+
+///Connection to OBS for tokio, the async counterpart of Websocket.
+///
+///A reader task sorts the recieved messages into responses and events. Requests only need `&self`,
+///so several of them can be in flight at the same time, their responses are matched by request id.
+///
+///Call close to complete the closing handshake. Dropping the connection sends the close frame
+///from a task on the current runtime without waiting for the server.
+///```no_run
+///# use obs_remote_1lt::{async_websocket::AsyncWebsocket, obs::OBSEventSubscription, requests::{GetSceneList, GetVersion}};
+///# async fn run() -> Result<(), obs_remote_1lt::error::Error> {
+///let obs = AsyncWebsocket::connect("ws://127.0.0.1:4455", None, 1, OBSEventSubscription::Scenes).await?;
+///let events = obs.events();
+///let (version, scenes) = tokio::join!(obs.call(&GetVersion{}), obs.call(&GetSceneList{}));
+///println!("OBS {} with {} scenes", version?.obs_version, scenes?.scenes.len());
+///# Ok(()) }
+///```
+pub struct AsyncWebsocket {
+    url: WebsocketUrl,
+    ip: SocketAddr,
+    encoding: Encoding,
+    compression: Option<DeflateParameters>,
+    event_subscriptions: OBSEventSubscription,
+    request_timeout: Duration,
+    shared: Arc<Shared>,
+    state: watch::Receiver<State>,
+    reader: JoinHandle<()>
+}
+
+///Events of an AsyncWebsocket, see AsyncWebsocket::events. Ends after the connection was closed.
+pub struct EventStream {
+    receiver: mpsc::Receiver<Event>
+}
+
+//state shared between the client and its reader task
+struct Shared {
+    //frames are compressed and written under this lock, so they reach the server in the order of their compression
+    writer: tokio::sync::Mutex<WriteHalf<Box<dyn Transport>>>,
+    deflate: Mutex<Option<Deflate>>,
+    pending: Mutex<HashMap<String, oneshot::Sender<Payload>>>,
+    event_streams: Mutex<Vec<mpsc::Sender<Event>>>,
+    close_frame: Mutex<Option<CloseFrame>>,
+    state: watch::Sender<State>
+}
+
+//reads and reassembles the frames of the connection, runs as reader task once the connection is identified
+struct FrameReader {
+    stream: ReadHalf<Box<dyn Transport>>,
+    assembler: Assembler,
+    shared: Arc<Shared>
+}
+
+//removes the request from the pending requests when the request finished, timed out or was cancelled
+struct PendingRequest<'a> {
+    shared: &'a Shared,
+    request_id: String
+}
+
+impl AsyncWebsocket {
+    ///Connects to OBS at a ws:// or wss:// url, see Websocket::connect
+    pub async fn connect(url: &str, password: Option<String>, rpcversion: usize, event_subscriptions: OBSEventSubscription) -> Result<AsyncWebsocket, Error> {
+        AsyncWebsocket::connect_with(WebsocketUrl::parse(url)?, &ConnectOptions::default(), password, rpcversion, event_subscriptions).await
+    }
+
+    ///Connects with the TLS, compression and encoding settings of options, see Websocket::connect_with.
+    ///Has to be called within a tokio runtime, the reader task is spawned on it.
+    pub async fn connect_with(url: WebsocketUrl, options: &ConnectOptions, password: Option<String>, rpcversion: usize, event_subscriptions: OBSEventSubscription) -> Result<AsyncWebsocket, Error> {
+        #[cfg(feature = "tls")]
+        let (url, tls) = match (&options.tls, url.is_secure()) {
+            (Some(tls), _) => (url.with_tls(), Some(tls.clone())),
+            (None, true) => (url, Some(TlsConfig::default())),
+            (None, false) => (url, None)
+        };
+        #[cfg(not(feature = "tls"))]
+        if url.is_secure() {
+            return Err(Error::websocket(WebsocketError::InvalidUrl, Some(format!("{url} needs the tls feature"))));
+        }
+
+        let (ip, stream) = AsyncWebsocket::connect_tcp(&url).await?;
+        #[cfg(feature = "tls")]
+        let stream: Box<dyn Transport> = match tls {
+            Some(tls) => AsyncWebsocket::start_tls(&url, ip, &tls, stream).await?,
+            None => Box::new(stream)
+        };
+        #[cfg(not(feature = "tls"))]
+        let stream: Box<dyn Transport> = Box::new(stream);
+
+        match timeout(TIMEOUT, AsyncWebsocket::identify(url, ip, stream, options, password, rpcversion, event_subscriptions)).await {
+            Ok(result) => result,
+            Err(_) => Err(Error::io(WebsocketError::ReadError, io::Error::new(ErrorKind::TimedOut, "handshake timed out")))
+        }
+    }
+
+    //tries the resolved addresses in order and returns the first connection, or the error of the last address
+    async fn connect_tcp(url: &WebsocketUrl) -> Result<(SocketAddr, TcpStream), Error> {
+        let addresses = match tokio::net::lookup_host((url.host(), url.port())).await {
+            Ok(addresses) => addresses,
+            Err(error) => return Err(Error::io(WebsocketError::ResolveError, error))
+        };
+        let mut last_error = io::Error::new(ErrorKind::NotFound, format!("{} did not resolve to any address", url.host()));
+        for address in addresses {
+            debug!("Connecting to {address}");
+            match timeout(TIMEOUT, TcpStream::connect(address)).await {
+                Ok(Ok(stream)) => return Ok((address, stream)),
+                Ok(Err(error)) => last_error = error,
+                Err(_) => last_error = io::Error::new(ErrorKind::TimedOut, "connection timed out")
+            }
+            debug!("Could not connect to {address}: {last_error}");
+        }
+        Err(Error::io(WebsocketError::ConnectError, last_error))
+    }
+
+    #[cfg(feature = "tls")]
+    async fn start_tls(url: &WebsocketUrl, ip: SocketAddr, tls: &TlsConfig, stream: TcpStream) -> Result<Box<dyn Transport>, Error> {
+        let connector = tokio_rustls::TlsConnector::from(tls.client_config()?);
+        match timeout(TIMEOUT, connector.connect(tls.server_name_for(url.host())?, stream)).await {
+            Ok(Ok(stream)) => {
+                debug!("TLS connection to {ip} established");
+                Ok(Box::new(stream))
+            },
+            Ok(Err(error)) => Err(Error::io(WebsocketError::TlsHandshakeError, error)),
+            Err(_) => Err(Error::io(WebsocketError::TlsHandshakeError, io::Error::new(ErrorKind::TimedOut, "TLS handshake timed out")))
+        }
+    }
+
+    async fn identify(url: WebsocketUrl, ip: SocketAddr, mut stream: Box<dyn Transport>, options: &ConnectOptions, password: Option<String>, rpcversion: usize, event_subscriptions: OBSEventSubscription) -> Result<AsyncWebsocket, Error> {
+        //Upgrade connection
+        let sec_websocket_key = handshake::new_sec_websocket_key();
+        let extensions = handshake::offer_extensions(options)?;
+        let request = handshake::request_upgrade_message(&url, sec_websocket_key.clone(), options.encoding, extensions.as_deref());
+        if let Err(error) = stream.write_all(request.as_bytes()).await {
+            return Err(Error::io(WebsocketError::WriteError, error));
+        }
+        let response = AsyncWebsocket::read_upgrade_response(&mut stream).await?;
+        let accepted_extensions = handshake::confirm_upgrade_response(response, sec_websocket_key, options.encoding)?;
+        let deflate = handshake::accept_extensions(options, accepted_extensions)?;
+        let compression = deflate.as_ref().map(Deflate::parameters);
+
+        let (read, write) = tokio::io::split(stream);
+        let (state_sender, state) = watch::channel(State::Initialising);
+        let shared = Arc::new(Shared{
+            writer: tokio::sync::Mutex::new(write),
+            deflate: Mutex::new(deflate),
+            pending: Mutex::new(HashMap::new()),
+            event_streams: Mutex::new(Vec::new()),
+            close_frame: Mutex::new(None),
+            state: state_sender
+        });
+        let mut reader = FrameReader{stream: read, assembler: Assembler::new(), shared: shared.clone()};
+
+        //OBS Protocol initialisation
+        //read opcode 0 | Hello
+        let hello = encoding::decode(&reader.read_message().await?)?;
+
+        //send opcode 1 | Identify
+        let identify = handshake::identify_message(hello, rpcversion, password, event_subscriptions)?;
+        shared.send(options.encoding.encode(&identify)?).await?;
+
+        //read opcode 2 | Identified
+        let identified = encoding::decode(&reader.read_message().await?)?;
+        if identified.opcode() != OBSOpcode::Identifyed {
+            return Err(Error::websocket(WebsocketError::NotIdentified, Some(identified.data().to_string())));
+        }
+        info!("Connected to {url} ({ip}, rpc version {}, {})", identified.data()["negotiatedRpcVersion"], options.encoding.subprotocol());
+        shared.state.send_replace(State::Open);
+
+        Ok(AsyncWebsocket{
+            url, ip, compression, event_subscriptions, state,
+            encoding: options.encoding,
+            request_timeout: TIMEOUT,
+            shared,
+            reader: tokio::spawn(reader.run())
+        })
+    }
+
+    //reads byte by byte up to the empty line, a frame sent right after the response (Hello) stays in the stream
+    async fn read_upgrade_response(stream: &mut Box<dyn Transport>) -> Result<String, Error> {
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() >= handshake::MAX_UPGRADE_RESPONSE {
+                return Err(Error::websocket(WebsocketError::UpgradeError, Some("upgrade response too long".to_string())));
+            }
+            match stream.read_u8().await {
+                Ok(byte) => response.push(byte),
+                Err(error) => return Err(Error::io(WebsocketError::ReadError, error))
+            }
+        }
+
+        let response = String::from_utf8_lossy(&response).to_string();
+        debug!("Upgrade response:\n{}", response.trim_end());
+        Ok(response)
+    }
+
+    ///permessage-deflate parameters if the server agreed to compression
+    pub fn compression(&self) -> Option<DeflateParameters> {
+        self.compression
+    }
+
+    ///Encoding of the OBS messages, negotiated as subprotocol
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    ///Address the connection was made to
+    pub fn ip(&self) -> SocketAddr {
+        self.ip
+    }
+
+    pub fn url(&self) -> &WebsocketUrl {
+        &self.url
+    }
+
+    pub fn state(&self) -> State {
+        *self.state.borrow()
+    }
+
+    ///Event categories this session is subscribed to
+    pub fn event_subscriptions(&self) -> OBSEventSubscription {
+        self.event_subscriptions
+    }
+
+    ///The close frame recieved from the server, if the server closed the connection
+    pub fn close_frame(&self) -> Option<CloseFrame> {
+        lock(&self.shared.close_frame).clone()
+    }
+
+    ///Time to wait for the response of a single request
+    pub fn set_request_timeout(&mut self, timeout: Duration) {
+        self.request_timeout = timeout;
+    }
+
+    ///Sends a request and waits for its response, see Websocket::request.
+    ///The request is serialized right away, the returned future only borrows the connection.
+    pub fn request(&self, request_type: &str, request_data: Value) -> impl Future<Output = Result<Payload, Error>> + '_ {
+        let request_id = handshake::new_request_id();
+        let payload = Payload::opcode_request_message(request_type, &request_id, request_data);
+        debug!("Sending request {request_type} ({request_id})");
+
+        async move { self.exchange(request_id, payload).await?.check_request_status() }
+    }
+
+    ///Sends a typed request and returns its typed response, see Websocket::call
+    pub fn call<R: Request>(&self, request: &R) -> impl Future<Output = Result<R::Response, Error>> + '_ {
+        let response = request.request_data().map(|request_data| self.request(R::REQUEST_TYPE, request_data));
+        async move { response?.await?.typed_response() }
+    }
+
+    ///Sends a request batch and waits for its response, see Websocket::request_batch
+    pub fn request_batch(&self, batch: &RequestBatch) -> impl Future<Output = Result<Vec<Payload>, Error>> + '_ {
+        let request_id = handshake::new_request_id();
+        let payload = Payload::opcode_request_batch_message(&request_id, batch);
+        debug!("Sending request batch of {} requests ({request_id})", batch.len());
+
+        async move { Ok(self.exchange(request_id, payload).await?.batch_results()) }
+    }
+
+    //sends a request message and waits until the reader task recieved the response with the same request id
+    async fn exchange(&self, request_id: String, payload: Value) -> Result<Payload, Error> {
+        if self.state() != State::Open {
+            return Err(self.shared.closed_error());
+        }
+        let (sender, receiver) = oneshot::channel();
+        let _pending = PendingRequest::new(&self.shared, request_id.clone(), sender)?;
+
+        self.shared.send(self.encoding.encode(&payload)?).await?;
+        match timeout(self.request_timeout, receiver).await {
+            Ok(Ok(payload)) => Ok(payload),
+            //the reader task drops the pending requests when the connection ends
+            Ok(Err(_)) => Err(self.shared.closed_error()),
+            Err(_) => Err(Error::websocket(WebsocketError::RequestTimeout, Some(request_id)))
+        }
+    }
+
+    ///Stream of the events recieved from now on. Every stream gets every event, events recieved before the call are not included.
+    ///A stream that is not polled buffers up to 1024 events, further events are dropped for this stream.
+    pub fn events(&self) -> EventStream {
+        let (sender, receiver) = mpsc::channel(EVENT_STREAM_CAPACITY);
+        let mut event_streams = lock(&self.shared.event_streams);
+        //the sender is dropped right away on a closed connection, which ends the stream
+        if self.state() != State::Closed {
+            event_streams.push(sender);
+        }
+        EventStream{receiver}
+    }
+
+    ///Closes the connection with the given status code and reason, see Websocket::close.
+    ///Requests still in flight fail.
+    pub async fn close(&self, code: u16, reason: &str) -> Result<(), Error> {
+        if self.state() == State::Closed {
+            return Ok(());
+        }
+
+        debug!("Closing connection ({code} {reason})");
+        self.shared.state.send_replace(State::Closing);
+        let result = self.shared.send(CloseFrame::new(code, reason).to_message()).await;
+        //the reader task ends when the close frame of the server arrives
+        let mut state = self.state.clone();
+        if result.is_err() || timeout(TIMEOUT, state.wait_for(|state| *state == State::Closed)).await.is_err() {
+            self.reader.abort();
+            self.shared.closed();
+            self.shared.shutdown().await;
+        }
+        result
+    }
+}
+
+//Drop can not wait for the closing handshake. The reader task is aborted and the close frame is sent by a task
+//spawned on the current runtime, which does not wait for the answer of the server.
+impl Drop for AsyncWebsocket {
+    fn drop(&mut self) {
+        self.reader.abort();
+        if self.state() == State::Open {
+            //the close frame can only be sent from within the runtime, without it the connection is just dropped
+            match Handle::try_current() {
+                Ok(runtime) => {
+                    let shared = self.shared.clone();
+                    runtime.spawn(async move {
+                        if let Err(error) = shared.send(CloseFrame::new(NORMAL_CLOSURE, "").to_message()).await {
+                            warn!("Could not send the close frame of a dropped connection: {error}");
+                        }
+                        shared.shutdown().await;
+                    });
+                },
+                Err(_) => warn!("AsyncWebsocket dropped outside of a tokio runtime, the connection ends without close frame")
+            }
+        }
+        self.shared.closed();
+    }
+}
+
+impl EventStream {
+    ///Waits for the next event, None after the connection was closed
+    pub async fn recv(&mut self) -> Option<Event> {
+        self.receiver.recv().await
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Shared {
+    //frames are compressed and written under the writer lock, see codec::frame_bytes
+    async fn send(&self, message: Message) -> Result<(), Error> {
+        let mut writer = self.writer.lock().await;
+        let bytes = codec::frame_bytes(message, lock(&self.deflate).as_mut())?;
+        match timeout(TIMEOUT, async { writer.write_all(&bytes).await?; writer.flush().await }).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(error)) => Err(Error::io(WebsocketError::WriteError, error)),
+            Err(_) => Err(Error::io(WebsocketError::WriteError, io::Error::new(ErrorKind::TimedOut, "write timed out")))
+        }
+    }
+
+    //hands a recieved message to the event streams or the request waiting for it
+    fn dispatch(&self, payload: Payload) {
+        match payload.route() {
+            Ok(Route::Event(event)) => lock(&self.event_streams).retain(|stream| match stream.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    warn!("Event stream full, dropping event");
+                    true
+                },
+                Err(mpsc::error::TrySendError::Closed(_)) => false
+            }),
+            Ok(Route::Response(id, payload)) => match lock(&self.pending).remove(&id) {
+                Some(sender) => {
+                    debug!("Recieved response to {id}");
+                    let _ = sender.send(payload);
+                },
+                //response to an unknown, timed out or cancelled request
+                None => warn!("Dropping response to unknown request {id}")
+            },
+            Ok(Route::Other(payload)) => debug!("Ignoring message with opcode {:?}", payload.opcode()),
+            Err(error) => warn!("Dropping invalid event: {error}")
+        }
+    }
+
+    //fails the pending requests and ends the event streams
+    fn closed(&self) {
+        self.state.send_replace(State::Closed);
+        lock(&self.pending).clear();
+        lock(&self.event_streams).clear();
+    }
+
+    async fn shutdown(&self) {
+        let mut writer = self.writer.lock().await;
+        let _ = timeout(TIMEOUT, writer.shutdown()).await;
+    }
+
+    fn closed_error(&self) -> Error {
+        Error::closed(lock(&self.close_frame).clone())
+    }
+}
+
+impl<'a> PendingRequest<'a> {
+    fn new(shared: &'a Shared, request_id: String, sender: oneshot::Sender<Payload>) -> Result<PendingRequest<'a>, Error> {
+        let mut pending = lock(&shared.pending);
+        //checked under the lock, Shared::closed clears the pending requests after changing the state
+        if *shared.state.borrow() == State::Closed {
+            return Err(shared.closed_error());
+        }
+        pending.insert(request_id.clone(), sender);
+        Ok(PendingRequest{shared, request_id})
+    }
+}
+
+impl Drop for PendingRequest<'_> {
+    fn drop(&mut self) {
+        lock(&self.shared.pending).remove(&self.request_id);
+    }
+}
+
+impl FrameReader {
+    async fn run(mut self) {
+        loop {
+            let message = match self.read_message().await {
+                Ok(message) => message,
+                Err(error) => {
+                    debug!("Connection ended: {error}");
+                    break;
+                }
+            };
+            match encoding::decode(&message) {
+                Ok(payload) => self.shared.dispatch(payload),
+                Err(error) => warn!("Dropping invalid message: {error}")
+            }
+        }
+        self.shared.closed();
+        self.shared.shutdown().await;
+    }
+
+    //same checks as Websocket::read_message
+    async fn read_message(&mut self) -> Result<Message, Error> {
+        match self.next_message().await {
+            Ok(message) => Ok(message),
+            Err(violation) => {
+                if let Some((code, reason)) = violation.close {
+                    self.fail(code, reason).await;
+                }
+                Err(violation.error)
+            }
+        }
+    }
+
+    async fn next_message(&mut self) -> Result<Message, Violation> {
+        loop {
+            let frame = self.read_frame().await?;
+            if frame.header().is_control_frame() {
+                self.handle_control_frame(frame).await?;
+                continue;
+            }
+
+            let message = self.assembler.push(frame, lock(&self.shared.deflate).as_mut())?;
+            if let Some(message) = message {
+                return Ok(message);
+            }
+        }
+    }
+
+    async fn handle_control_frame(&mut self, frame: Message) -> Result<(), Violation> {
+        let closing = *self.shared.state.borrow() == State::Closing;
+        match codec::control_frame(&frame, closing)? {
+            Control::Ping(pong) => Ok(self.shared.send(pong).await?),
+            Control::Pong => Ok(()),
+            Control::Close{close_frame, reply} => {
+                if let Some(reply) = reply {
+                    let _ = self.shared.send(reply).await;
+                }
+                *lock(&self.shared.close_frame) = Some(close_frame.clone());
+                Err(Error::ConnectionClosed(close_frame).into())
+            }
+        }
+    }
+
+    //sends a close frame after a protocol violation of the server, the reader task ends with the returned error
+    async fn fail(&self, code: u16, reason: &str) {
+        self.shared.state.send_replace(State::Closing);
+        let _ = self.shared.send(CloseFrame::new(code, reason).to_message()).await;
+    }
+
+    ///Reads exactly one frame, see codec::read_frame
+    async fn read_frame(&mut self) -> Result<Message, Violation> {
+        //read header
+        let mut bytes = vec![0u8; 2];
+        self.read(&mut bytes).await?;
+        bytes.resize(Header::required_header_size([bytes[0], bytes[1]]), 0);
+        self.read(&mut bytes[2..]).await?;
+
+        //read mask if set and payload
+        let header_size = bytes.len();
+        bytes.resize(header_size + codec::check_header(&bytes, &self.assembler)?, 0);
+        self.read(&mut bytes[header_size..]).await?;
+
+        codec::parse_frame(bytes, &self.assembler)
+    }
+
+    async fn read(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        match self.stream.read_exact(buffer).await {
+            Ok(_) => Ok(()),
+            Err(error) => Err(Error::io(WebsocketError::ReadError, error))
+        }
+    }
+}
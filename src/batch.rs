@@ -0,0 +1,55 @@
+use serde_json::Value;
+
+use crate::error::Error;
+use crate::obs::OBSRequestBatchExecutionType;
+use crate::requests::Request;
+
+///A batch of requests that is sent to OBS in one message (opcode 8), see Websocket::request_batch.
+///
+///SerialRealtime processes the requests one after another as fast as possible,
+///SerialFrame processes them in sync with the graphics thread so all changes become visible in the same frame,
+///Parallel processes them at the same time without a defined order.
+pub struct RequestBatch {
+    execution_type: OBSRequestBatchExecutionType,
+    halt_on_failure: bool,
+    requests: Vec<(String, Value)>
+}
+
+impl RequestBatch {
+    ///halt_on_failure stops processing the batch at the first failed request (not possible with Parallel)
+    pub fn new(execution_type: OBSRequestBatchExecutionType, halt_on_failure: bool) -> RequestBatch {
+        RequestBatch{execution_type, halt_on_failure, requests: Vec::new()}
+    }
+
+    ///Queues a request. The results of the batch are returned in the order the requests were added.
+    pub fn add(&mut self, request_type: &str, request_data: Value) {
+        self.requests.push((request_type.to_string(), request_data));
+    }
+
+    ///Queues a typed request, its response can be parsed with Payload::typed_response::<R::Response>.
+    ///Fails if the request data cannot be serialized, see Request::request_data.
+    pub fn add_request<R: Request>(&mut self, request: &R) -> Result<(), Error> {
+        self.add(R::REQUEST_TYPE, request.request_data()?);
+        Ok(())
+    }
+
+    pub fn execution_type(&self) -> OBSRequestBatchExecutionType {
+        self.execution_type
+    }
+
+    pub fn halt_on_failure(&self) -> bool {
+        self.halt_on_failure
+    }
+
+    pub fn requests(&self) -> &[(String, Value)] {
+        &self.requests
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+}
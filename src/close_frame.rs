@@ -0,0 +1,78 @@
+use std::fmt;
+
+use crate::{header::Opcode, message::Message, obs::{OBSWebSocketCloseCode, obs_websocket_close_code_match_enum}};
+
+///Status code 1000, the connection fulfilled its purpose
+pub const NORMAL_CLOSURE: u16 = 1000;
+///Status code 1002, the other side violated the WebSocket protocol
+pub const PROTOCOL_ERROR: u16 = 1002;
+///Status code 1007, a message contained data that does not match its type (e.g. invalid UTF-8 in a text message)
+pub const INVALID_FRAME_PAYLOAD_DATA: u16 = 1007;
+///Status code 1009, a frame or message is too big to be processed
+pub const MESSAGE_TOO_BIG: u16 = 1009;
+
+///Content of a WebSocket close frame.
+///OBS uses the status code to tell why it closed the connection (see OBSWebSocketCloseCode).
+#[derive(Clone, PartialEq, Debug)]
+pub struct CloseFrame {
+    code: Option<u16>,
+    reason: String
+}
+
+impl CloseFrame {
+    pub fn new(code: u16, reason: &str) -> CloseFrame {
+        CloseFrame{code: Some(code), reason: reason.to_string()}
+    }
+
+    pub fn code(&self) -> Option<u16> {
+        self.code
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+
+    ///OBS specific close code, None if the code is not an OBS close code (e.g. 1000 normal closure)
+    pub fn obs_close_code(&self) -> Option<OBSWebSocketCloseCode> {
+        match self.code {
+            Some(code) => obs_websocket_close_code_match_enum(&code.to_string()).ok(),
+            None => None
+        }
+    }
+
+    ///The payload of a close frame is optional. If present, it starts with a 2 byte status code followed by the reason.
+    pub fn from_message(message: &Message) -> CloseFrame {
+        let payload = message.payload_bytes();
+        if payload.len() >= 2 {
+            let code = u16::from_be_bytes([payload[0], payload[1]]);
+            let reason = String::from_utf8_lossy(&payload[2..]).to_string();
+            CloseFrame{code: Some(code), reason}
+        }
+        else {
+            CloseFrame{code: None, reason: String::new()}
+        }
+    }
+
+    pub fn to_message(&self) -> Message {
+        let mut payload: Vec<u8> = Vec::new();
+        if let Some(code) = self.code {
+            payload.extend(code.to_be_bytes());
+            payload.extend(self.reason.as_bytes());
+        }
+        Message::with_bytes(true, Opcode::ConnectionClose, true, payload)
+    }
+}
+
+impl fmt::Display for CloseFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.code, self.obs_close_code()) {
+            (Some(code), Some(obs_code)) => write!(f, "{code} {obs_code:?}")?,
+            (Some(code), None) => write!(f, "{code}")?,
+            (None, _) => write!(f, "no status code")?
+        }
+        if !self.reason.is_empty() {
+            write!(f, " ({})", self.reason)?;
+        }
+        Ok(())
+    }
+}
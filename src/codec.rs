@@ -0,0 +1,283 @@
+use std::io::Read;
+
+use log::{info, log_enabled, trace, Level};
+
+use crate::{close_frame::{CloseFrame, INVALID_FRAME_PAYLOAD_DATA, MESSAGE_TOO_BIG, PROTOCOL_ERROR}, deflate::Deflate, encoding, error::Error, header::{Header, Opcode}, message::Message, payload::Payload, websocket::{WebsocketError, FRAME_LOG_TARGET}};
+
+//Reading, reassembling and tracing of frames, shared by the blocking, threaded and async clients
+
+///Frames with a larger payload fail the connection with status code 1009 before their payload is read
+const MAX_FRAME_SIZE: u64 = 16 * 1024 * 1024;
+///Fragmented messages can not grow larger than this, see MAX_FRAME_SIZE
+const MAX_MESSAGE_SIZE: u64 = 64 * 1024 * 1024;
+
+///Reassembles fragmented messages from their continuation frames and decompresses them
+pub(crate) struct Assembler {
+    fragments: Option<Message>
+}
+
+///Control frame recieved from the server and what the client has to do about it
+pub(crate) enum Control {
+    ///A ping, answer it with this pong
+    Ping(Message),
+    ///A pong answering a ping of the client
+    Pong,
+    ///The server closed the connection. reply echoes the status code to complete the closing handshake,
+    ///it is None if the client started the closing handshake itself.
+    Close{close_frame: CloseFrame, reply: Option<Message>}
+}
+
+///A message that violates the protocol. The connection is failed with the status code and reason of close if set.
+pub(crate) struct Violation {
+    pub(crate) error: Error,
+    pub(crate) close: Option<(u16, &'static str)>
+}
+
+impl Assembler {
+    pub(crate) fn new() -> Assembler {
+        Assembler{fragments: None}
+    }
+
+    ///Header of the first frame of the message a frame belongs to.
+    ///Continuation frames are compressed and binary like the first frame of their message.
+    pub(crate) fn first_header<'a>(&'a self, frame: &'a Message) -> &'a Header {
+        match (frame.header().get_value_opcode(), self.fragments.as_ref()) {
+            (Opcode::ContinuationFrame, Some(message)) => message.header(),
+            _ => frame.header()
+        }
+    }
+
+    ///Adds a data frame. Returns the message once its last frame arrived, decompressed if deflate is given.
+    ///A text message that is not valid UTF-8 is a violation with status code 1007.
+    pub(crate) fn push(&mut self, frame: Message, deflate: Option<&mut Deflate>) -> Result<Option<Message>, Violation> {
+        let fin = frame.header().has_fin_byte_set();
+        //only the first frame of a message marks it as compressed
+        if frame.header().has_rsv1_set() && (deflate.is_none() || frame.header().get_value_opcode() == Opcode::ContinuationFrame) {
+            let error = Error::websocket(WebsocketError::DeflateError, Some("RSV1 set on a frame that can not be compressed".to_string()));
+            return Err(Violation{error, close: Some((PROTOCOL_ERROR, "unexpected RSV1"))});
+        }
+        let mut message = match (frame.header().get_value_opcode(), self.fragments.take()) {
+            (Opcode::ContinuationFrame, Some(mut message)) => {
+                message.append_fragment(frame);
+                message
+            },
+            (Opcode::ContinuationFrame, None) => return Err(Violation::fragmentation("continuation frame without a preceding data frame")),
+            (_, Some(_)) => return Err(Violation::fragmentation("new data frame before the fragmented message was finished")),
+            (_, None) => frame
+        };
+        if !fin {
+            self.fragments = Some(message);
+            return Ok(None);
+        }
+
+        if let (Some(deflate), true) = (deflate, message.header().has_rsv1_set()) {
+            match deflate.decompress(message.payload_bytes()) {
+                Ok(payload) => {
+                    message.replace_payload(payload, false);
+                    trace_frame("decompressed", &message, message.header());
+                },
+                Err(error) => return Err(Violation{error, close: Some((INVALID_FRAME_PAYLOAD_DATA, "invalid compressed data"))})
+            }
+        }
+        //invalid text fails the connection
+        if let Err(error) = message.check_utf8() {
+            return Err(Violation{error: error.into(), close: Some((INVALID_FRAME_PAYLOAD_DATA, "invalid UTF-8"))});
+        }
+        Ok(Some(message))
+    }
+}
+
+impl Violation {
+    fn fragmentation(information: &str) -> Violation {
+        let error = Error::websocket(WebsocketError::FragmentationError, Some(information.to_string()));
+        Violation{error, close: Some((PROTOCOL_ERROR, "invalid fragmentation"))}
+    }
+
+    fn too_big(information: String) -> Violation {
+        Violation{error: Error::websocket(WebsocketError::MessageTooBig, Some(information)), close: Some((MESSAGE_TOO_BIG, "message too big"))}
+    }
+}
+
+//errors that are no protocol violation, e.g. a failed read, end the connection without close frame
+impl From<Error> for Violation {
+    fn from(error: Error) -> Violation {
+        Violation{error, close: None}
+    }
+}
+
+///Reads exactly one frame, see check_header and parse_frame
+pub(crate) fn read_frame<R: Read>(stream: &mut R, assembler: &Assembler) -> Result<Message, Violation> {
+    //read header
+    let mut bytes = vec![0u8; 2];
+    read(stream, &mut bytes)?;
+    bytes.resize(Header::required_header_size([bytes[0], bytes[1]]), 0);
+    read(stream, &mut bytes[2..])?;
+
+    //read mask if set and payload
+    let header_size = bytes.len();
+    bytes.resize(header_size + check_header(&bytes, assembler)?, 0);
+    read(stream, &mut bytes[header_size..])?;
+
+    parse_frame(bytes, assembler)
+}
+
+fn read<R: Read>(stream: &mut R, buffer: &mut [u8]) -> Result<(), Error> {
+    match stream.read_exact(buffer) {
+        Ok(()) => Ok(()),
+        Err(error) => Err(Error::io(WebsocketError::ReadError, error))
+    }
+}
+
+///Checks the complete header of a frame before the rest of the frame is read.
+///Returns the number of bytes that follow the header (mask and payload).
+///Frames larger than MAX_FRAME_SIZE and messages growing larger than MAX_MESSAGE_SIZE are a violation with status code 1009.
+pub(crate) fn check_header(bytes: &[u8], assembler: &Assembler) -> Result<usize, Violation> {
+    let header = match Header::from_bytes(bytes.to_vec()) {
+        Ok(header) => header,
+        Err(error) => return Err(Violation{error: error.into(), close: Some((PROTOCOL_ERROR, "invalid frame header"))})
+    };
+    check_control_frame(&header)?;
+
+    let length = header.get_value_payload_length();
+    if length > MAX_FRAME_SIZE {
+        return Err(Violation::too_big(format!("frame of {length} bytes")));
+    }
+    let buffered = match (header.is_control_frame(), assembler.fragments.as_ref()) {
+        (false, Some(message)) => message.payload_bytes().len() as u64,
+        _ => 0
+    };
+    if buffered + length > MAX_MESSAGE_SIZE {
+        return Err(Violation::too_big(format!("fragmented message of more than {MAX_MESSAGE_SIZE} bytes")));
+    }
+
+    let mask_length = if header.has_mask_byte_set() { 4 } else { 0 };
+    Ok(mask_length + length as usize)
+}
+
+///Parses a frame read after check_header
+pub(crate) fn parse_frame(bytes: Vec<u8>, assembler: &Assembler) -> Result<Message, Violation> {
+    let frame = Message::from_bytes(bytes)?;
+    trace_frame("recieved", &frame, assembler.first_header(&frame));
+    Ok(frame)
+}
+
+///Control frames can not be fragmented and carry at most 125 bytes, other control frames fail the connection with status code 1002
+fn check_control_frame(header: &Header) -> Result<(), Violation> {
+    if header.is_control_frame() && (!header.has_fin_byte_set() || header.get_value_payload_length() > 125) {
+        let error = Error::websocket(WebsocketError::ControlFrameError, None);
+        return Err(Violation{error, close: Some((PROTOCOL_ERROR, "invalid control frame"))});
+    }
+    Ok(())
+}
+
+///Decides how to answer a control frame. closing is true if the client already sent its close frame.
+pub(crate) fn control_frame(frame: &Message, closing: bool) -> Result<Control, Violation> {
+    match frame.header().get_value_opcode() {
+        Opcode::Ping => Ok(Control::Ping(Message::with_bytes(true, Opcode::Pong, true, frame.payload_bytes().to_vec()))),
+        Opcode::Pong => Ok(Control::Pong),
+        _ => {
+            let close_frame = close_frame(frame)?;
+            info!("Connection closed by the server: {close_frame}");
+            let reply = (!closing).then(|| close_frame.to_message());
+            Ok(Control::Close{close_frame, reply})
+        }
+    }
+}
+
+///Bytes of a frame to send. Complete text and binary messages are compressed if permessage-deflate was negotiated.
+///Frames have to be written in the order they were compressed, the compression context is shared between them.
+pub(crate) fn frame_bytes(mut message: Message, deflate: Option<&mut Deflate>) -> Result<Vec<u8>, Error> {
+    let header = message.header();
+    if let (Some(deflate), true) = (deflate, header.has_fin_byte_set() && matches!(header.get_value_opcode(), Opcode::TextFrame | Opcode::BinaryFrame)) {
+        trace_frame("compressing", &message, message.header());
+        let compressed = deflate.compress(message.payload_bytes())?;
+        message.replace_payload(compressed, true);
+    }
+    trace_frame("sent", &message, message.header());
+    Ok(message.to_bytes())
+}
+
+///Parses the payload of a close frame. A payload of a single byte, a status code that must not be sent
+///or a reason that is not valid UTF-8 fail the connection with status code 1002.
+pub(crate) fn close_frame(frame: &Message) -> Result<CloseFrame, Violation> {
+    let payload = frame.payload_bytes();
+    let close_frame = CloseFrame::from_message(frame);
+    let valid = match close_frame.code() {
+        Some(code) => matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999) && std::str::from_utf8(&payload[2..]).is_ok(),
+        None => payload.is_empty()
+    };
+    if !valid {
+        let error = Error::websocket(WebsocketError::ControlFrameError, Some(format!("invalid close frame payload {payload:?}")));
+        return Err(Violation{error, close: Some((PROTOCOL_ERROR, "invalid close frame"))});
+    }
+    Ok(close_frame)
+}
+
+//first is the header of the first frame of the message, which tells if a continuation frame is compressed or binary
+pub(crate) fn trace_frame(direction: &str, frame: &Message, first: &Header) {
+    if !log_enabled!(target: FRAME_LOG_TARGET, Level::Trace) {
+        return;
+    }
+
+    let header = frame.header();
+    let payload = match first.get_value_opcode() {
+        _ if first.has_rsv1_set() => "<compressed>".to_string(),
+        Opcode::TextFrame => Payload::redact(&frame.payload()),
+        //complete MessagePack messages are shown as JSON
+        Opcode::BinaryFrame if header.get_value_opcode() == Opcode::BinaryFrame && header.has_fin_byte_set() => match encoding::decode_message_pack(frame.payload_bytes()) {
+            Ok(json) => Payload::redact(&json.to_string()),
+            Err(_) => format!("{:?}", frame.payload_bytes())
+        },
+        _ => format!("{:?}", frame.payload_bytes())
+    };
+    trace!(target: FRAME_LOG_TARGET, "{direction} {:?} fin={} rsv1={} length={} {payload}",
+        header.get_value_opcode(), header.has_fin_byte_set(), header.has_rsv1_set(), header.get_value_payload_length());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{close_frame::INVALID_FRAME_PAYLOAD_DATA, error::{Error, MessageError}, header::Opcode, message::Message};
+
+    use super::{read_frame, Assembler};
+
+    #[test]
+    fn invalid_utf8_text_fails_with_1007() {
+        let mut assembler = Assembler::new();
+        //a two byte character split between two fragments is valid
+        assert!(matches!(assembler.push(Message::with_bytes(false, Opcode::TextFrame, false, vec![b'a', 0xc3]), None), Ok(None)));
+        match assembler.push(Message::with_bytes(true, Opcode::ContinuationFrame, false, vec![0xbc]), None) {
+            Ok(Some(message)) => assert_eq!(message.payload(), "aü"),
+            _ => panic!("fragmented text was not reassembled")
+        }
+
+        match assembler.push(Message::with_bytes(true, Opcode::TextFrame, false, vec![b'a', 0xff, 0xfe]), None) {
+            Err(violation) => {
+                assert!(matches!(violation.error, Error::Message(MessageError::InvalidUtf8)));
+                assert_eq!(violation.close.map(|(code, _)| code), Some(INVALID_FRAME_PAYLOAD_DATA));
+            },
+            _ => panic!("invalid UTF-8 was accepted")
+        }
+    }
+
+    #[test]
+    fn binary_frames_keep_their_bytes() {
+        let payload = vec![0x00, 0xff, 0x80, 0xc3, 0x0a, 0xfe];
+        let mut bytes = vec![0x02, 3];
+        bytes.extend_from_slice(&payload[..3]);
+        bytes.extend_from_slice(&[0x80, 3]);
+        bytes.extend_from_slice(&payload[3..]);
+
+        let mut stream = bytes.as_slice();
+        let mut assembler = Assembler::new();
+        let first = read_frame(&mut stream, &assembler).ok().unwrap();
+        assert!(matches!(assembler.push(first, None), Ok(None)));
+        let last = read_frame(&mut stream, &assembler).ok().unwrap();
+        match assembler.push(last, None) {
+            Ok(Some(message)) => {
+                assert_eq!(message.header().get_value_opcode(), Opcode::BinaryFrame);
+                assert_eq!(message.payload_bytes(), payload.as_slice());
+            },
+            _ => panic!("fragmented binary message was not reassembled")
+        }
+    }
+}
@@ -0,0 +1,435 @@
+use std::{collections::BTreeMap, env, fs, path::{Path, PathBuf}};
+
+use serde::Deserialize;
+
+#[cfg(feature = "tls")]
+use obs_remote_1lt::tls::TlsConfig;
+use obs_remote_1lt::encoding::Encoding;
+use obs_remote_1lt::url::WebsocketUrl;
+
+const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 4455;
+const DEFAULT_TLS_PORT: u16 = 443;
+const DEFAULT_RPC_VERSION: usize = 1;
+
+///Connection settings as given by one source (config file, environment or command line).
+///Every value is optional, unset values are taken from the source with the next lower precedence.
+#[derive(Clone, Default, Debug, Deserialize)]
+pub struct ConnectionSettings {
+    ///ws://host:port/path or wss://host:port/path, sets host, port, path and tls at once
+    ///(port 4455 for ws://, 443 for wss:// if the url has none)
+    pub url: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    ///Request path of the upgrade request, "/" if not set
+    pub path: Option<String>,
+    pub password: Option<String>,
+    ///File containing the password, relative paths in the config file are relative to the config file
+    pub password_file: Option<PathBuf>,
+    pub rpc_version: Option<usize>,
+    ///Connect with TLS (wss://)
+    pub tls: Option<bool>,
+    ///PEM file with the certificates to trust for TLS instead of the Mozilla root certificates,
+    ///relative paths in the config file are relative to the config file
+    pub ca_file: Option<PathBuf>,
+    ///SHA-256 fingerprint of the server certificate, only this certificate is accepted
+    pub pin_sha256: Option<String>,
+    ///Offer permessage-deflate compression
+    pub compress: Option<bool>,
+    ///Encoding of the OBS messages, "json" or "msgpack"
+    pub encoding: Option<String>
+}
+
+///Content of the config file.
+///The top level settings apply to every profile, a profile only needs the values that differ:
+///```toml
+///password_file = "obs-password"
+///default_profile = "stream"
+///
+///[profiles.stream]
+///url = "ws://192.168.1.20:4455"
+///
+///[profiles.record]
+///url = "ws://192.168.1.21:4455"
+///```
+#[derive(Default, Debug, Deserialize)]
+pub struct ConfigFile {
+    #[serde(flatten)]
+    pub connection: ConnectionSettings,
+    ///Profile used when no --profile is given
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ConnectionSettings>
+}
+
+///Final connection settings after all sources were merged
+#[derive(Debug)]
+pub struct Connection {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+    pub password: Option<String>,
+    pub rpc_version: usize,
+    pub tls: bool,
+    pub ca_file: Option<PathBuf>,
+    pub pin_sha256: Option<String>,
+    pub compress: bool,
+    pub encoding: Encoding
+}
+
+///Options and arguments of the command line
+#[derive(Default, Debug)]
+pub struct CommandLine {
+    pub config: Option<PathBuf>,
+    pub connection: ConnectionSettings,
+    ///Profiles from --profile, a command is run on each of them
+    pub profiles: Vec<String>,
+    pub verbose: bool,
+    pub trace_frames: bool,
+    ///Command and its arguments, starting with the program name
+    pub args: Vec<String>
+}
+
+impl CommandLine {
+    ///Options can be given anywhere as `--option value` or `--option=value`, everything else is an argument.
+    ///Everything after `--` is an argument, e.g. a scene name starting with `-`.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<CommandLine, String> {
+        let mut command_line = CommandLine::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                command_line.args.extend(args.by_ref());
+                break;
+            }
+            if !arg.starts_with('-') {
+                command_line.args.push(arg);
+                continue;
+            }
+
+            let (option, inline_value) = match arg.split_once('=') {
+                Some((option, value)) => (option.to_string(), Some(value.to_string())),
+                None => (arg, None)
+            };
+            let mut value = || inline_value.clone().or_else(|| args.next()).ok_or_else(|| format!("Missing value for {option}"));
+
+            match option.as_str() {
+                "-v" | "--verbose" => command_line.verbose = true,
+                "--trace-frames" => command_line.trace_frames = true,
+                "--config" => command_line.config = Some(PathBuf::from(value()?)),
+                "--profile" => command_line.profiles.extend(value()?.split(',').filter(|name| !name.is_empty()).map(str::to_string)),
+                "--url" => command_line.connection.url = Some(value()?),
+                "--host" => command_line.connection.host = Some(value()?),
+                "--port" => command_line.connection.port = Some(value()?.parse().map_err(|_| "--port is not a valid port".to_string())?),
+                "--password" => command_line.connection.password = Some(value()?),
+                "--password-file" => command_line.connection.password_file = Some(PathBuf::from(value()?)),
+                "--rpc-version" => command_line.connection.rpc_version = Some(value()?.parse().map_err(|_| "--rpc-version is not a number".to_string())?),
+                "--tls" => command_line.connection.tls = Some(true),
+                "--ca-file" => command_line.connection.ca_file = Some(PathBuf::from(value()?)),
+                "--pin-sha256" => command_line.connection.pin_sha256 = Some(value()?),
+                "--compress" => command_line.connection.compress = Some(true),
+                "--encoding" => command_line.connection.encoding = Some(value()?),
+                _ => return Err(format!("Unknown option {option}"))
+            }
+        }
+
+        Ok(command_line)
+    }
+}
+
+impl ConnectionSettings {
+    ///Settings from OBS_WEBSOCKET_URL, OBS_WEBSOCKET_PASSWORD, OBS_WEBSOCKET_PASSWORD_FILE, OBS_WEBSOCKET_RPC_VERSION,
+    ///OBS_WEBSOCKET_CA_FILE and OBS_WEBSOCKET_PIN_SHA256
+    pub fn from_env() -> Result<ConnectionSettings, String> {
+        let rpc_version = match env::var("OBS_WEBSOCKET_RPC_VERSION") {
+            Ok(version) => Some(version.parse().map_err(|_| format!("OBS_WEBSOCKET_RPC_VERSION is not a number: {version}"))?),
+            Err(_) => None
+        };
+
+        Ok(ConnectionSettings {
+            url: env::var("OBS_WEBSOCKET_URL").ok(),
+            host: None,
+            port: None,
+            path: None,
+            password: env::var("OBS_WEBSOCKET_PASSWORD").ok(),
+            password_file: env::var_os("OBS_WEBSOCKET_PASSWORD_FILE").map(PathBuf::from),
+            rpc_version,
+            tls: None,
+            ca_file: env::var_os("OBS_WEBSOCKET_CA_FILE").map(PathBuf::from),
+            pin_sha256: env::var("OBS_WEBSOCKET_PIN_SHA256").ok(),
+            compress: None,
+            encoding: None
+        })
+    }
+
+    ///Splits the url into host, port, path and tls. Values given next to the url take precedence.
+    pub fn normalized(self) -> Result<ConnectionSettings, String> {
+        let (tls, host, port, path) = match &self.url {
+            Some(url) => {
+                let url = WebsocketUrl::parse_with_default_port(url, DEFAULT_PORT).map_err(|error| error.to_string())?;
+                (Some(url.is_secure()), Some(url.host().to_string()), Some(url.port()), Some(url.path().to_string()))
+            },
+            None => (None, None, None, None)
+        };
+
+        Ok(ConnectionSettings {
+            url: None,
+            host: self.host.or(host),
+            port: self.port.or(port),
+            path: self.path.or(path),
+            tls: self.tls.or(tls),
+            ..self
+        })
+    }
+
+    ///Fills every value that is not set from other (both normalized).
+    ///A password or password file counts as one value, so a password given here is not replaced by a password file from other.
+    pub fn or(self, other: ConnectionSettings) -> ConnectionSettings {
+        let has_password = self.password.is_some() || self.password_file.is_some();
+
+        ConnectionSettings {
+            url: None,
+            host: self.host.or(other.host),
+            port: self.port.or(other.port),
+            path: self.path.or(other.path),
+            password: if has_password { self.password } else { other.password },
+            password_file: if has_password { self.password_file } else { other.password_file },
+            rpc_version: self.rpc_version.or(other.rpc_version),
+            tls: self.tls.or(other.tls),
+            ca_file: self.ca_file.or(other.ca_file),
+            pin_sha256: self.pin_sha256.or(other.pin_sha256),
+            compress: self.compress.or(other.compress),
+            encoding: self.encoding.or(other.encoding)
+        }
+    }
+
+    ///Applies the defaults (127.0.0.1:4455 without TLS, no password, rpc version 1) and reads the password file
+    pub fn resolve(self) -> Result<Connection, String> {
+        let settings = self.normalized()?;
+        let password = match (settings.password, settings.password_file) {
+            (Some(_), Some(_)) => return Err("password and password_file are both set, use only one of them".to_string()),
+            (Some(password), None) => Some(password),
+            (None, Some(path)) => Some(read_password_file(&path)?),
+            (None, None) => None
+        };
+
+        let encoding = match settings.encoding.as_deref() {
+            None | Some("json") => Encoding::Json,
+            Some("msgpack") => Encoding::MessagePack,
+            Some(encoding) => return Err(format!("Unknown encoding {encoding}, use json or msgpack"))
+        };
+
+        let tls = settings.tls.unwrap_or(false);
+        Ok(Connection {
+            host: settings.host.unwrap_or_else(|| DEFAULT_HOST.to_string()),
+            port: settings.port.unwrap_or(if tls { DEFAULT_TLS_PORT } else { DEFAULT_PORT }),
+            path: settings.path.unwrap_or_else(|| "/".to_string()),
+            password,
+            rpc_version: settings.rpc_version.unwrap_or(DEFAULT_RPC_VERSION),
+            tls,
+            ca_file: settings.ca_file,
+            pin_sha256: settings.pin_sha256,
+            compress: settings.compress.unwrap_or(false),
+            encoding
+        })
+    }
+}
+
+impl ConfigFile {
+    ///Reads the config file. A missing file is only an error if the path was given explicitly.
+    pub fn load(path: Option<&Path>) -> Result<ConfigFile, String> {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_config_path() {
+                Some(path) => (path, false),
+                None => return Ok(ConfigFile::default())
+            }
+        };
+
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(_) if !explicit && !path.exists() => return Ok(ConfigFile::default()),
+            Err(error) => return Err(format!("Could not read config file {}: {error}", path.display()))
+        };
+        let mut config: ConfigFile = toml::from_str(&content)
+            .map_err(|error| format!("Invalid config file {}: {error}", path.display()))?;
+
+        //password and certificate files are relative to the config file
+        if let Some(directory) = path.parent() {
+            for settings in std::iter::once(&mut config.connection).chain(config.profiles.values_mut()) {
+                settings.password_file = settings.password_file.take().map(|file| directory.join(expand_home(&file)));
+                settings.ca_file = settings.ca_file.take().map(|file| directory.join(expand_home(&file)));
+            }
+        }
+        Ok(config)
+    }
+
+    ///Profiles to use: the ones given on the command line, else the default profile.
+    ///An empty list means the top level settings are used without a profile.
+    pub fn selected_profiles(&self, command_line: &CommandLine) -> Vec<String> {
+        if command_line.profiles.is_empty() {
+            self.default_profile.iter().cloned().collect()
+        }
+        else {
+            command_line.profiles.clone()
+        }
+    }
+
+    ///Settings of a profile, without the top level settings
+    pub fn profile(&self, name: &str) -> Result<ConnectionSettings, String> {
+        match self.profiles.get(name) {
+            Some(profile) => profile.clone().normalized(),
+            None => {
+                let known: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+                Err(format!("Unknown profile {name} (profiles in the config file: {})", if known.is_empty() { "none".to_string() } else { known.join(", ") }))
+            }
+        }
+    }
+
+    ///Merges all sources: command line options, then the selected profile, then the environment, then the top level settings
+    pub fn connection(&self, command_line: &CommandLine, environment: &ConnectionSettings, profile: Option<&str>) -> Result<Connection, String> {
+        let mut settings = command_line.connection.clone().normalized()?;
+        if let Some(name) = profile {
+            settings = settings.or(self.profile(name)?);
+        }
+        settings.or(environment.clone()).or(self.connection.clone().normalized()?).resolve()
+    }
+}
+
+impl Connection {
+    ///Url to connect to, the host name is resolved when connecting and every address is tried
+    pub fn url(&self) -> WebsocketUrl {
+        let url = WebsocketUrl::new(&self.host, self.port, &self.path);
+        if self.tls {
+            url.with_tls()
+        }
+        else {
+            url
+        }
+    }
+
+    ///Certificates to trust: the CA file or else the Mozilla root certificates, or only the pinned certificate
+    #[cfg(feature = "tls")]
+    pub fn tls_config(&self) -> Result<TlsConfig, String> {
+        let mut tls = TlsConfig::new();
+        if let Some(file) = &self.ca_file {
+            tls = tls.without_webpki_roots()
+                .add_root_certificates_pem(expand_home(file))
+                .map_err(|error| error.to_string())?;
+        }
+        if let Some(fingerprint) = &self.pin_sha256 {
+            tls = tls.pin_sha256_hex(fingerprint).map_err(|error| error.to_string())?;
+        }
+        Ok(tls)
+    }
+}
+
+///$XDG_CONFIG_HOME/obs-remote/config.toml or ~/.config/obs-remote/config.toml, %APPDATA%\obs-remote\config.toml on Windows
+pub fn default_config_path() -> Option<PathBuf> {
+    let directory = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    }
+    else {
+        env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+    directory.map(|directory| directory.join("obs-remote").join("config.toml"))
+}
+
+fn read_password_file(path: &Path) -> Result<String, String> {
+    let path = expand_home(path);
+    match fs::read_to_string(&path) {
+        Ok(password) => Ok(password.trim_end_matches(['\r', '\n']).to_string()),
+        Err(error) => Err(format!("Could not read password file {}: {error}", path.display()))
+    }
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => path.to_path_buf()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{CommandLine, ConfigFile, ConnectionSettings};
+
+    fn settings(url: Option<&str>, port: Option<u16>, password: Option<&str>, password_file: Option<&str>) -> ConnectionSettings {
+        ConnectionSettings {
+            url: url.map(str::to_string),
+            port,
+            password: password.map(str::to_string),
+            password_file: password_file.map(PathBuf::from),
+            ..Default::default()
+        }.normalized().unwrap()
+    }
+
+    #[test]
+    fn higher_precedence_wins_per_value() {
+        let flags = settings(None, Some(4456), None, None);
+        let env = settings(Some("ws://obs.local:4455"), None, Some("env"), None);
+        let file = settings(Some("ws://10.0.0.2"), None, None, None);
+
+        let connection = flags.or(env).or(file).resolve().unwrap();
+        assert_eq!(connection.host, "obs.local");
+        assert_eq!(connection.port, 4456);
+        assert_eq!(connection.password.as_deref(), Some("env"));
+        assert_eq!(connection.rpc_version, 1);
+    }
+
+    #[test]
+    fn password_replaces_password_file_of_lower_precedence() {
+        let env = settings(None, None, Some("env"), None);
+        let file = settings(None, None, None, Some("/does/not/exist"));
+
+        assert_eq!(env.or(file).resolve().unwrap().password.as_deref(), Some("env"));
+    }
+
+    #[test]
+    fn parse_url_and_options() {
+        let command_line = CommandLine::parse(["obs-remote", "--url=ws://[::1]:4460/", "show_scene", "-v", "--rpc-version", "1", "Scene"].map(String::from)).unwrap();
+
+        assert_eq!(command_line.args, ["obs-remote", "show_scene", "Scene"]);
+        assert!(command_line.verbose);
+        let connection = command_line.connection.resolve().unwrap();
+        assert_eq!((connection.host.as_str(), connection.port), ("::1", 4460));
+        assert_eq!(connection.url().to_string(), "ws://[::1]:4460/");
+        let connection = settings(Some("wss://obs.example.org/obs"), None, None, None).resolve().unwrap();
+        assert_eq!(connection.url().to_string(), "wss://obs.example.org/obs");
+        assert!(CommandLine::parse(["obs-remote", "--port"].map(String::from)).is_err());
+        assert!(CommandLine::parse(["obs-remote", "show_scene", "-Intro-"].map(String::from)).is_err());
+        let command_line = CommandLine::parse(["obs-remote", "-v", "show_scene", "--", "-Intro-", "--verbose"].map(String::from)).unwrap();
+        assert_eq!(command_line.args, ["obs-remote", "show_scene", "-Intro-", "--verbose"]);
+        assert!(ConnectionSettings{url: Some("http://obs".to_string()), ..Default::default()}.normalized().is_err());
+        assert!(ConnectionSettings{url: Some("ws://user@obs".to_string()), ..Default::default()}.normalized().is_err());
+        let connection = settings(Some("WS://obs.local/obs"), None, None, None).resolve().unwrap();
+        assert_eq!((connection.host.as_str(), connection.port, connection.path.as_str()), ("obs.local", 4455, "/obs"));
+        assert_eq!(settings(Some("ws://obs.local:80"), None, None, None).resolve().unwrap().port, 80);
+    }
+
+    #[test]
+    fn profile_beats_environment_beats_top_level() {
+        let config: ConfigFile = toml::from_str(r#"
+            password = "shared"
+            [profiles.stream]
+            url = "ws://10.0.0.2:4455"
+            [profiles.record]
+            url = "ws://10.0.0.3"
+            password = "record"
+        "#).unwrap();
+        let command_line = CommandLine::parse(["obs-remote", "--profile", "stream,record", "set_chapter"].map(String::from)).unwrap();
+        let environment = settings(Some("ws://obs.local:4460"), None, Some("env"), None);
+
+        assert_eq!(config.selected_profiles(&command_line), ["stream", "record"]);
+        let stream = config.connection(&command_line, &environment, Some("stream")).unwrap();
+        assert_eq!((stream.host.as_str(), stream.port, stream.password.as_deref()), ("10.0.0.2", 4455, Some("env")));
+        let record = config.connection(&command_line, &environment, Some("record")).unwrap();
+        assert_eq!((record.host.as_str(), record.port, record.password.as_deref()), ("10.0.0.3", 4455, Some("record")));
+        assert!(config.connection(&command_line, &environment, Some("missing")).is_err());
+        let stream = config.connection(&command_line, &ConnectionSettings::default(), Some("stream")).unwrap();
+        assert_eq!(stream.password.as_deref(), Some("shared"));
+    }
+}
@@ -0,0 +1,47 @@
+use crate::deflate::DeflateConfig;
+use crate::encoding::Encoding;
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
+
+///Optional features of a connection, see Websocket::connect_with.
+///```no_run
+///# use obs_remote_1lt::{connect_options::ConnectOptions, deflate::DeflateConfig, obs::OBSEventSubscription, url::WebsocketUrl, websocket::Websocket};
+///# fn main() -> Result<(), obs_remote_1lt::error::Error> {
+///let options = ConnectOptions::new().deflate(DeflateConfig::default());
+///let websocket = Websocket::connect_with(WebsocketUrl::parse("ws://obs.local:4455")?, &options, None, 1, OBSEventSubscription::All)?;
+///# Ok(()) }
+///```
+#[derive(Clone, Debug, Default)]
+pub struct ConnectOptions {
+    pub(crate) deflate: Option<DeflateConfig>,
+    pub(crate) encoding: Encoding,
+    #[cfg(feature = "tls")]
+    pub(crate) tls: Option<TlsConfig>
+}
+
+impl ConnectOptions {
+    pub fn new() -> ConnectOptions {
+        ConnectOptions::default()
+    }
+
+    ///Offers permessage-deflate compression, used if the server agrees
+    pub fn deflate(mut self, config: DeflateConfig) -> ConnectOptions {
+        self.deflate = Some(config);
+        self
+    }
+
+    ///Sends and recieves the OBS messages as JSON (default) or MessagePack.
+    ///Connecting fails if the server does not agree to the encoding.
+    pub fn encoding(mut self, encoding: Encoding) -> ConnectOptions {
+        self.encoding = encoding;
+        self
+    }
+
+    ///Connects over TLS with these settings, also for ws:// urls.
+    ///wss:// urls without TLS settings use TlsConfig::default().
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, config: TlsConfig) -> ConnectOptions {
+        self.tls = Some(config);
+        self
+    }
+}
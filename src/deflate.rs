@@ -0,0 +1,271 @@
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+use crate::error::Error;
+use crate::websocket::WebsocketError;
+
+//Every compressed message ends with an empty stored block, which is removed before sending (RFC 7692 section 7.2.1)
+const EMPTY_BLOCK_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+///Decompressed messages larger than this fail the connection
+const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+///permessage-deflate compression (RFC 7692) offered in the upgrade request, see ConnectOptions::deflate.
+///The server decides whether it is used, Websocket::compression shows the outcome.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DeflateConfig {
+    ///Compression level of sent messages, 0 (none) to 9 (best)
+    pub level: u32,
+    ///Reset the compression of sent messages after every message.
+    ///Saves the memory of the compression context at the cost of worse compression.
+    pub client_no_context_takeover: bool,
+    ///Ask the server to reset its compression after every message
+    pub server_no_context_takeover: bool,
+    ///Ask the server to use a smaller LZ77 window (8 to 15 bits), recieved messages then need less memory
+    pub server_max_window_bits: Option<u8>
+}
+
+impl Default for DeflateConfig {
+    fn default() -> DeflateConfig {
+        DeflateConfig{level: 6, client_no_context_takeover: false, server_no_context_takeover: false, server_max_window_bits: None}
+    }
+}
+
+///Parameters of permessage-deflate the server agreed to
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DeflateParameters {
+    pub client_no_context_takeover: bool,
+    pub server_no_context_takeover: bool,
+    pub server_max_window_bits: Option<u8>
+}
+
+impl DeflateConfig {
+    ///Value of the Sec-WebSocket-Extensions header.
+    ///client_max_window_bits is not offered, sent messages always use a 15 bit window.
+    pub(crate) fn offer(&self) -> Result<String, Error> {
+        let mut offer = "permessage-deflate".to_string();
+        if self.client_no_context_takeover {
+            offer.push_str("; client_no_context_takeover");
+        }
+        if self.server_no_context_takeover {
+            offer.push_str("; server_no_context_takeover");
+        }
+        if let Some(bits) = self.server_max_window_bits {
+            if !(8..=15).contains(&bits) {
+                return Err(Error::websocket(WebsocketError::DeflateError, Some(format!("server_max_window_bits has to be between 8 and 15, not {bits}"))));
+            }
+            offer.push_str(&format!("; server_max_window_bits={bits}"));
+        }
+        if self.level > 9 {
+            return Err(Error::websocket(WebsocketError::DeflateError, Some(format!("compression level has to be between 0 and 9, not {}", self.level))));
+        }
+        Ok(offer)
+    }
+
+    ///Checks the Sec-WebSocket-Extensions header of the upgrade response against the offer
+    pub(crate) fn accept(&self, response: &str) -> Result<Deflate, Error> {
+        let invalid = |reason: &str| Error::websocket(WebsocketError::UpgradeExtensionError, Some(format!("{reason}: {response}")));
+
+        let mut parts = response.split(';').map(str::trim);
+        if !parts.next().is_some_and(|name| name.eq_ignore_ascii_case("permessage-deflate")) {
+            return Err(invalid("extension was not offered"));
+        }
+
+        let mut parameters = DeflateParameters{
+            client_no_context_takeover: self.client_no_context_takeover,
+            server_no_context_takeover: false,
+            server_max_window_bits: None
+        };
+        let mut seen = Vec::new();
+        for parameter in parts {
+            let (name, value) = match parameter.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (parameter, None)
+            };
+            if seen.contains(&name) {
+                return Err(invalid("duplicate parameter"));
+            }
+            seen.push(name);
+
+            match (name, value) {
+                ("client_no_context_takeover", None) => parameters.client_no_context_takeover = true,
+                ("server_no_context_takeover", None) => parameters.server_no_context_takeover = true,
+                ("server_max_window_bits", Some(bits)) => match bits.parse() {
+                    Ok(bits) if (8..=15).contains(&bits) => parameters.server_max_window_bits = Some(bits),
+                    _ => return Err(invalid("invalid server_max_window_bits"))
+                },
+                //client_max_window_bits may only be sent if the client offered it
+                _ => return Err(invalid("unsupported parameter"))
+            }
+        }
+        if self.server_no_context_takeover && !parameters.server_no_context_takeover {
+            return Err(invalid("server_no_context_takeover was requested"));
+        }
+        if self.server_max_window_bits.is_some_and(|requested| parameters.server_max_window_bits.is_none_or(|bits| bits > requested)) {
+            return Err(invalid("server_max_window_bits was requested"));
+        }
+
+        Ok(Deflate::new(self.level, parameters))
+    }
+}
+
+//compression context of a connection
+pub(crate) struct Deflate {
+    parameters: DeflateParameters,
+    compress: Compress,
+    decompress: Decompress
+}
+
+impl Deflate {
+    fn new(level: u32, parameters: DeflateParameters) -> Deflate {
+        Deflate{parameters, compress: Compress::new(Compression::new(level), false), decompress: Decompress::new(false)}
+    }
+
+    pub(crate) fn parameters(&self) -> DeflateParameters {
+        self.parameters
+    }
+
+    pub(crate) fn compress(&mut self, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        let start = self.compress.total_in();
+        let mut output = Vec::with_capacity(payload.len() / 2 + 64);
+        loop {
+            let consumed = self.compress.total_in() - start;
+            let written = output.len();
+            if let Err(error) = self.compress.compress_vec(&payload[consumed as usize..], &mut output, FlushCompress::Sync) {
+                return Err(deflate_error(error.to_string()));
+            }
+            let progressed = self.compress.total_in() - start > consumed || output.len() > written;
+            //the flush is complete when all input is consumed and the output was not filled up or nothing is left
+            if (self.compress.total_in() - start) as usize == payload.len() && (output.len() < output.capacity() || !progressed) {
+                break;
+            }
+            if !progressed {
+                return Err(deflate_error("compression made no progress".to_string()));
+            }
+            if output.len() == output.capacity() {
+                output.reserve(output.capacity());
+            }
+        }
+
+        if output.ends_with(&EMPTY_BLOCK_TAIL) {
+            output.truncate(output.len() - EMPTY_BLOCK_TAIL.len());
+        }
+        if self.parameters.client_no_context_takeover {
+            self.compress.reset();
+        }
+        Ok(output)
+    }
+
+    pub(crate) fn decompress(&mut self, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut input = payload.to_vec();
+        input.extend_from_slice(&EMPTY_BLOCK_TAIL);
+
+        let start = self.decompress.total_in();
+        let mut output = Vec::with_capacity(payload.len() * 4 + 64);
+        loop {
+            let consumed = self.decompress.total_in() - start;
+            let written = output.len();
+            let status = match self.decompress.decompress_vec(&input[consumed as usize..], &mut output, FlushDecompress::Sync) {
+                Ok(status) => status,
+                Err(error) => return Err(deflate_error(error.to_string()))
+            };
+            //the message ended with a final block (RFC 7692 section 7.2.3.4), only the appended tail may follow it.
+            //The next message starts a new stream.
+            if status == Status::StreamEnd {
+                let rest = &input[(self.decompress.total_in() - start) as usize..];
+                self.decompress.reset(false);
+                if !rest.is_empty() && rest != EMPTY_BLOCK_TAIL {
+                    return Err(deflate_error("data after the end of the compressed message".to_string()));
+                }
+                return Ok(output);
+            }
+            let progressed = self.decompress.total_in() - start > consumed || output.len() > written;
+            if (self.decompress.total_in() - start) as usize == input.len() && (output.len() < output.capacity() || !progressed) {
+                break;
+            }
+            if !progressed {
+                return Err(deflate_error("decompression made no progress".to_string()));
+            }
+            //checked before growing, so the buffer never exceeds the limit by more than one byte
+            if output.len() == output.capacity() {
+                if output.capacity() > MAX_DECOMPRESSED_SIZE {
+                    return Err(deflate_error(format!("message larger than {MAX_DECOMPRESSED_SIZE} bytes")));
+                }
+                output.reserve_exact((output.capacity() * 2).min(MAX_DECOMPRESSED_SIZE + 1) - output.len());
+            }
+        }
+
+        if self.parameters.server_no_context_takeover {
+            self.decompress.reset(false);
+        }
+        Ok(output)
+    }
+}
+
+fn deflate_error(reason: String) -> Error {
+    Error::websocket(WebsocketError::DeflateError, Some(reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::{Compress, Compression, FlushCompress};
+
+    use super::DeflateConfig;
+
+    //a message compressed into a single stream that ends with a final block
+    fn final_block(message: &[u8]) -> Vec<u8> {
+        let mut compress = Compress::new(Compression::default(), false);
+        let mut output = Vec::with_capacity(message.len() + 64);
+        compress.compress_vec(message, &mut output, FlushCompress::Finish).unwrap();
+        output
+    }
+
+    #[test]
+    fn negotiate_parameters() {
+        let config = DeflateConfig{server_no_context_takeover: true, server_max_window_bits: Some(10), ..Default::default()};
+        assert_eq!(config.offer().unwrap(), "permessage-deflate; server_no_context_takeover; server_max_window_bits=10");
+
+        let parameters = config.accept("permessage-deflate; server_no_context_takeover; server_max_window_bits=9").unwrap().parameters();
+        assert!(parameters.server_no_context_takeover && !parameters.client_no_context_takeover);
+        assert_eq!(parameters.server_max_window_bits, Some(9));
+
+        assert!(config.accept("permessage-deflate; server_max_window_bits=10").is_err());
+        assert!(DeflateConfig::default().accept("permessage-deflate; client_max_window_bits=10").is_err());
+        assert!(DeflateConfig::default().accept("permessage-deflate; client_no_context_takeover; client_no_context_takeover").is_err());
+        assert!(DeflateConfig::default().accept("x-webkit-deflate-frame").is_err());
+    }
+
+    #[test]
+    fn messages_share_the_context_unless_reset() {
+        let message = br#"{"op":5,"d":{"eventType":"InputVolumeMeters","eventData":{"inputs":[]}}}"#;
+        for client_no_context_takeover in [false, true] {
+            let config = DeflateConfig{client_no_context_takeover, ..Default::default()};
+            let mut sender = config.accept("permessage-deflate").unwrap();
+            let mut reciever = config.accept("permessage-deflate").unwrap();
+
+            let first = sender.compress(message).unwrap();
+            let second = sender.compress(message).unwrap();
+            //the second message refers to the first one if the context is kept
+            assert_eq!(second.len() < first.len(), !client_no_context_takeover);
+            assert_eq!(reciever.decompress(&first).unwrap(), message);
+            assert_eq!(reciever.decompress(&second).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn message_may_end_with_a_final_block() {
+        let message = br#"{"op":5,"d":{"eventType":"ExitStarted","eventIntent":1}}"#;
+        let mut reciever = DeflateConfig::default().accept("permessage-deflate").unwrap();
+        let mut sender = DeflateConfig::default().accept("permessage-deflate").unwrap();
+        assert_eq!(reciever.decompress(&final_block(message)).unwrap(), message);
+        //the next message is a new stream
+        assert_eq!(reciever.decompress(&final_block(message)).unwrap(), message);
+        assert_eq!(reciever.decompress(&sender.compress(message).unwrap()).unwrap(), message);
+    }
+
+    #[test]
+    fn bytes_after_the_end_of_the_stream_fail() {
+        let mut payload = final_block(b"{}");
+        payload.extend_from_slice(b"garbage");
+        let mut reciever = DeflateConfig::default().accept("permessage-deflate").unwrap();
+        assert!(reciever.decompress(&payload).is_err());
+    }
+}
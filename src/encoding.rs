@@ -0,0 +1,71 @@
+use serde_json::Value;
+
+use crate::error::Error;
+use crate::header::Opcode;
+use crate::message::Message;
+use crate::payload::{Payload, PayloadError};
+
+///Encoding of the OBS messages, negotiated as WebSocket subprotocol in the upgrade request.
+///JSON is sent in text frames, MessagePack in binary frames. MessagePack is smaller and faster to parse,
+///which helps with high-frequency events like InputVolumeMeters.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MessagePack
+}
+
+impl Encoding {
+    ///Value of the Sec-WebSocket-Protocol header
+    pub fn subprotocol(&self) -> &'static str {
+        match self {
+            Encoding::Json => "obswebsocket.json",
+            Encoding::MessagePack => "obswebsocket.msgpack"
+        }
+    }
+
+    ///Frame with a message built by the Payload::opcode_*_message functions
+    pub(crate) fn encode(&self, message: &Value) -> Result<Message, Error> {
+        match self {
+            Encoding::Json => Ok(Message::new(true, Opcode::TextFrame, true, message.to_string())),
+            Encoding::MessagePack => match rmp_serde::to_vec_named(message) {
+                Ok(bytes) => Ok(Message::with_bytes(true, Opcode::BinaryFrame, true, bytes)),
+                Err(error) => Err(Error::payload(PayloadError::InvalidMessagePack, Some(error.to_string())))
+            }
+        }
+    }
+}
+
+///Parses a message from OBS, text frames as JSON and binary frames as MessagePack
+pub(crate) fn decode(message: &Message) -> Result<Payload, Error> {
+    match message.header().get_value_opcode() {
+        Opcode::BinaryFrame => Payload::from_value(decode_message_pack(message.payload_bytes())?),
+        _ => Payload::from_string(message.payload())
+    }
+}
+
+pub(crate) fn decode_message_pack(bytes: &[u8]) -> Result<Value, Error> {
+    rmp_serde::from_slice(bytes).map_err(|error| Error::payload(PayloadError::InvalidMessagePack, Some(error.to_string())))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{decode, decode_message_pack, Encoding};
+    use crate::header::Opcode;
+    use crate::obs::{OBSEventSubscription, OBSOpcode};
+    use crate::payload::Payload;
+
+    #[test]
+    fn message_pack_round_trip() {
+        let identify = Encoding::MessagePack.encode(&Payload::opcode_identify_message(1, None, OBSEventSubscription::All)).unwrap();
+        assert_eq!(identify.header().get_value_opcode(), Opcode::BinaryFrame);
+        assert_eq!(decode_message_pack(identify.payload_bytes()).unwrap()["d"]["rpcVersion"], 1);
+
+        let identified = Encoding::MessagePack.encode(&json!({"op": 2, "d": {"negotiatedRpcVersion": 1}})).unwrap();
+        let payload = decode(&identified).unwrap();
+        assert_eq!(payload.opcode(), OBSOpcode::Identifyed);
+        assert_eq!(payload.data()["negotiatedRpcVersion"], 1);
+    }
+}
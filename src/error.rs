@@ -0,0 +1,128 @@
+use std::{error, fmt, io};
+
+use crate::close_frame::CloseFrame;
+use crate::obs::{OBSRequestStatus, OBSWebSocketCloseCode};
+use crate::request_status::RequestStatus;
+
+pub use crate::header::HeaderError;
+pub use crate::message::MessageError;
+pub use crate::obs::OBSMatchError;
+pub use crate::payload::PayloadError;
+pub use crate::websocket::WebsocketError;
+
+///Error returned by every fallible function of this crate.
+///
+///Failures can be told apart by matching on the variant and kind, e.g.
+///a wrong password closes the connection with OBSWebSocketCloseCode::AuthenticationFailed (see close_code)
+///and a missing scene fails the request with OBSRequestStatus::ResourceNotFound (see request_status).
+#[derive(Debug)]
+pub enum Error {
+    ///Connecting, reading from or writing to the connection failed
+    Io { kind: WebsocketError, source: io::Error },
+    ///The handshake, framing or identification failed, or the connection is not open
+    Websocket { kind: WebsocketError, information: Option<String> },
+    ///The server closed the connection, the close frame contains the OBS close code
+    ConnectionClosed(CloseFrame),
+    ///A frame header could not be parsed
+    Header(HeaderError),
+    ///A frame could not be parsed
+    Message(MessageError),
+    ///A message from OBS does not match the protocol
+    Payload { kind: PayloadError, information: Option<String> },
+    ///OBS could not execute a request
+    RequestFailed { request_type: String, status: RequestStatus },
+    ///A value sent by OBS does not exist in the protocol
+    OBSMatch { kind: OBSMatchError, information: Option<String> }
+}
+
+impl Error {
+    pub(crate) fn io(kind: WebsocketError, source: io::Error) -> Error {
+        Error::Io{kind, source}
+    }
+
+    pub(crate) fn websocket(kind: WebsocketError, information: Option<String>) -> Error {
+        Error::Websocket{kind, information}
+    }
+
+    pub(crate) fn payload(kind: PayloadError, information: Option<String>) -> Error {
+        Error::Payload{kind, information}
+    }
+
+    //error for a connection that ended, with the close frame of the server if it sent one
+    pub(crate) fn closed(close_frame: Option<CloseFrame>) -> Error {
+        match close_frame {
+            Some(close_frame) => Error::ConnectionClosed(close_frame),
+            None => Error::websocket(WebsocketError::NotConnected, None)
+        }
+    }
+
+    ///OBS close code if the server closed the connection with one
+    pub fn close_code(&self) -> Option<OBSWebSocketCloseCode> {
+        match self {
+            Error::ConnectionClosed(close_frame) => close_frame.obs_close_code(),
+            _ => None
+        }
+    }
+
+    ///Status sent by OBS if a request failed
+    pub fn request_status(&self) -> Option<&RequestStatus> {
+        match self {
+            Error::RequestFailed{status, ..} => Some(status),
+            _ => None
+        }
+    }
+
+    ///Status code of a failed request, shortcut for request_status().code()
+    pub fn request_status_code(&self) -> Option<OBSRequestStatus> {
+        self.request_status().map(RequestStatus::code)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io{kind, source} => write!(f, "{kind}: {source}"),
+            Error::Websocket{kind, information} => with_information(f, kind, information),
+            Error::ConnectionClosed(close_frame) => write!(f, "The connection was closed by the server: {close_frame}"),
+            Error::Header(kind) => write!(f, "{kind}"),
+            Error::Message(kind) => write!(f, "{kind}"),
+            Error::Payload{kind, information} => with_information(f, kind, information),
+            Error::RequestFailed{request_type, status} => write!(f, "OBS could not execute the request: {request_type}: {status}"),
+            Error::OBSMatch{kind, information} => with_information(f, kind, information)
+        }
+    }
+}
+
+fn with_information(f: &mut fmt::Formatter<'_>, kind: &dyn fmt::Display, information: &Option<String>) -> fmt::Result {
+    match information {
+        Some(information) => write!(f, "{kind}: {information}"),
+        None => write!(f, "{kind}")
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io{source, ..} => Some(source),
+            _ => None
+        }
+    }
+}
+
+impl From<HeaderError> for Error {
+    fn from(error: HeaderError) -> Error {
+        Error::Header(error)
+    }
+}
+
+impl From<MessageError> for Error {
+    fn from(error: MessageError) -> Error {
+        Error::Message(error)
+    }
+}
+
+impl From<OBSMatchError> for Error {
+    fn from(error: OBSMatchError) -> Error {
+        Error::OBSMatch{kind: error, information: None}
+    }
+}
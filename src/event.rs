@@ -0,0 +1,148 @@
+use log::{debug, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{error::Error, obs::OBSEventSubscription, payload::Payload, requests::null_as_default, websocket::{State, Websocket}};
+
+///Callback registered with Websocket::on_event
+pub type EventCallback = Box<dyn FnMut(&Event) + Send>;
+
+//Event enum with one variant per event of protocol/protocol.json (see build.rs)
+include!(concat!(env!("OUT_DIR"), "/events.rs"));
+
+impl Event {
+    ///Parses an event message. Events missing in protocol/protocol.json become Event::Unknown with the raw data,
+    ///as do known events whose data does not match the protocol (logged as warning).
+    pub fn from_payload(payload: &Payload) -> Result<Event, Error> {
+        let data = payload.data();
+        let event_type = data["eventType"].as_str().unwrap_or_default().to_string();
+        let event_data = data.get("eventData").cloned().unwrap_or(Value::Null);
+        //BroadcastCustomEvent sends any eventData as it is, or none at all
+        if event_type == "CustomEvent" {
+            return Ok(Event::CustomEvent{event_data});
+        }
+
+        let tagged = match event_data {
+            Value::Null => json!({"eventType": event_type}),
+            _ => json!({"eventType": event_type, "eventData": event_data})
+        };
+        match serde_json::from_value(tagged) {
+            Ok(event) => Ok(event),
+            Err(error) => {
+                if EVENT_TYPES.contains(&event_type.as_str()) {
+                    warn!("Could not parse event {event_type}, keeping its raw data: {error}");
+                }
+                else {
+                    debug!("Unknown event {event_type}");
+                }
+                let event_intent = match data["eventIntent"].as_u64() {
+                    Some(intent) => OBSEventSubscription::from_bits(intent as u32).unwrap_or(OBSEventSubscription::None),
+                    None => OBSEventSubscription::None
+                };
+                Ok(Event::Unknown{event_type, event_intent, event_data})
+            }
+        }
+    }
+
+    ///True if the category of the event is part of the given subscriptions,
+    ///e.g. `event.is_in(OBSEventSubscription::Scenes | OBSEventSubscription::SceneItems)`
+    pub fn is_in(&self, subscriptions: OBSEventSubscription) -> bool {
+        subscriptions.intersects(self.event_intent())
+    }
+}
+
+///Blocking iterator over the events of a websocket, see Websocket::events.
+///Ends after the connection was closed.
+pub struct Events<'a> {
+    websocket: &'a mut Websocket
+}
+
+impl<'a> Events<'a> {
+    pub fn new(websocket: &'a mut Websocket) -> Events<'a> {
+        Events{websocket}
+    }
+}
+
+impl Iterator for Events<'_> {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.websocket.state() == State::Closed && !self.websocket.has_buffered_events() {
+            return None;
+        }
+        Some(self.websocket.next_event())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::obs::OBSEventSubscription;
+    use crate::payload::Payload;
+
+    use super::Event;
+
+    fn event(message: serde_json::Value) -> Event {
+        Event::from_payload(&Payload::from_string(message.to_string()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn known_event_is_typed() {
+        let event = event(json!({"op": 5, "d": {
+            "eventType": "SceneItemEnableStateChanged",
+            "eventIntent": 128,
+            "eventData": {"sceneName": "Scene", "sceneUuid": "1234", "sceneItemId": 5, "sceneItemEnabled": true}
+        }}));
+
+        assert_eq!(event, Event::SceneItemEnableStateChanged {
+            scene_name: "Scene".to_string(),
+            scene_uuid: "1234".to_string(),
+            scene_item_id: 5,
+            scene_item_enabled: true
+        });
+        assert_eq!(event.event_intent(), OBSEventSubscription::SceneItems);
+        assert!(event.is_in(OBSEventSubscription::All));
+    }
+
+    #[test]
+    fn custom_event_keeps_the_whole_event_data() {
+        let event = event(json!({"op": 5, "d": {
+            "eventType": "CustomEvent",
+            "eventIntent": 1,
+            "eventData": {"realm": "chapters", "chapter": {"name": "Intro", "frame": 1200}}
+        }}));
+
+        assert_eq!(event, Event::CustomEvent {
+            event_data: json!({"realm": "chapters", "chapter": {"name": "Intro", "frame": 1200}})
+        });
+        assert_eq!(event.event_intent(), OBSEventSubscription::General);
+    }
+
+    #[test]
+    fn custom_event_without_event_data() {
+        for data in [json!({"eventType": "CustomEvent", "eventIntent": 1}), json!({"eventType": "CustomEvent", "eventIntent": 1, "eventData": null})] {
+            assert_eq!(event(json!({"op": 5, "d": data})), Event::CustomEvent{event_data: serde_json::Value::Null});
+        }
+    }
+
+    #[test]
+    fn event_without_data() {
+        let event = event(json!({"op": 5, "d": {"eventType": "ExitStarted", "eventIntent": 1}}));
+
+        assert_eq!(event, Event::ExitStarted);
+        assert_eq!(event.event_type(), "ExitStarted");
+    }
+
+    #[test]
+    fn unknown_event_keeps_raw_data() {
+        let event = event(json!({"op": 5, "d": {"eventType": "SomethingNew", "eventIntent": 4, "eventData": {"a": 1}}}));
+
+        assert_eq!(event, Event::Unknown {
+            event_type: "SomethingNew".to_string(),
+            event_intent: OBSEventSubscription::Scenes,
+            event_data: json!({"a": 1})
+        });
+        assert!(!event.is_in(OBSEventSubscription::Inputs));
+    }
+}
@@ -0,0 +1,192 @@
+use log::debug;
+use rand::RngCore;
+use base64::{Engine as _, engine::general_purpose};
+use sha1::{Sha1, Digest};
+use sha2::Sha256;
+use serde_json::Value;
+
+use crate::{connect_options::ConnectOptions, deflate::Deflate, encoding::Encoding, error::Error, obs::{OBSEventSubscription, OBSOpcode}, payload::Payload, url::WebsocketUrl, websocket::WebsocketError};
+
+//Upgrade request and Hello/Identify of the OBS protocol, shared by the blocking and the async client
+
+///Upgrade responses longer than this are rejected
+pub(crate) const MAX_UPGRADE_RESPONSE: usize = 16 * 1024;
+
+///Value of the Sec-WebSocket-Extensions header of the upgrade request
+pub(crate) fn offer_extensions(options: &ConnectOptions) -> Result<Option<String>, Error> {
+    match &options.deflate {
+        Some(deflate) => Ok(Some(deflate.offer()?)),
+        None => Ok(None)
+    }
+}
+
+///Checks the extensions accepted in the upgrade response against the offer
+pub(crate) fn accept_extensions(options: &ConnectOptions, accepted_extensions: Option<String>) -> Result<Option<Deflate>, Error> {
+    let deflate = match (&options.deflate, accepted_extensions) {
+        (Some(deflate), Some(extensions)) => Some(deflate.accept(&extensions)?),
+        (None, Some(extensions)) => return Err(Error::websocket(WebsocketError::UpgradeExtensionError, Some(format!("extension was not offered: {extensions}")))),
+        (_, None) => None
+    };
+    if let Some(deflate) = &deflate {
+        debug!("permessage-deflate negotiated: {:?}", deflate.parameters());
+    }
+    Ok(deflate)
+}
+
+///Builds the Identify message (opcode 1) answering the Hello message (opcode 0)
+pub(crate) fn identify_message(hello: Payload, rpcversion: usize, password: Option<String>, event_subscriptions: OBSEventSubscription) -> Result<Value, Error> {
+    if hello.opcode() != OBSOpcode::Hello {
+        return Err(Error::websocket(WebsocketError::MissingOpCode0, None));
+    }
+    debug!("Hello from obs-websocket {}, authentication {}",
+        hello.data()["obsWebSocketVersion"].as_str().unwrap_or_default(),
+        if hello.data().get("authentication").is_some() { "required" } else { "not required" });
+
+    opcode1_payload(rpcversion, password, event_subscriptions, hello)
+}
+
+pub(crate) fn request_upgrade_message(url: &WebsocketUrl, sec_websocket_key: String, encoding: Encoding, extensions: Option<&str>) -> String {
+    let mut str = format!("GET {} HTTP/1.1\r\n", url.path());
+    str = format!("{str}Host: {}\r\n", url.host_header());
+    str = format!("{str}Upgrade: websocket\r\n");
+    str = format!("{str}Connection: Upgrade\r\n");
+    str = format!("{str}Sec-WebSocket-Key: {sec_websocket_key}\r\n");
+    str = format!("{str}Sec-WebSocket-Protocol: {}\r\n", encoding.subprotocol());
+    str = format!("{str}Sec-WebSocket-Version: 13\r\n");
+    if let Some(extensions) = extensions {
+        str = format!("{str}Sec-WebSocket-Extensions: {extensions}\r\n");
+    }
+    str = format!("{str}\r\n");
+    debug!("Upgrade request:\n{}", str.trim_end());
+    str
+}
+
+pub(crate) fn confirm_upgrade_response(message: String, sec_websocket_key: String, encoding: Encoding) -> Result<Option<String>, Error> {
+    let lines: Vec<&str> = message.split("\r\n").collect();
+
+    //Check for HTTP Status
+    if lines[0] != "HTTP/1.1 101 Switching Protocols" {
+        if lines[0].starts_with("HTTP/1.1") {
+            return Err(Error::websocket(WebsocketError::UpgradeError, Some(lines[0].to_string())));
+        }
+        else {
+            return Err(Error::websocket(WebsocketError::UpgradeError, Some(message)));
+        }
+
+    }
+
+    check_upgrade_response_values(lines, sec_websocket_key, encoding)
+}
+
+fn check_upgrade_response_values(lines: Vec<&str>, sec_websocket_key: String, encoding: Encoding) -> Result<Option<String>, Error> {
+    let mut websocket = false;
+    let mut upgrade = false;
+    let mut accept = false;
+    let mut protocol = false;
+    let mut extensions: Option<String> = None;
+
+    for line in &lines[1..] {
+        match *line {
+            "Upgrade: websocket" => {websocket = true; continue;},
+            "Connection: Upgrade" => {upgrade = true; continue;},
+            _ => ()
+        }
+        if line.starts_with("Sec-WebSocket-Accept:") {
+            accept = check_sec_websocket_accept(line.replace("Sec-WebSocket-Accept: ", ""), sec_websocket_key.clone());
+            continue;
+        }
+        if line.starts_with("Sec-WebSocket-Protocol:") {
+            if line.replace("Sec-WebSocket-Protocol: ", "") == encoding.subprotocol() {
+                protocol = true;
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            //the header may be repeated, the values are joined like a comma separated list
+            if name.eq_ignore_ascii_case("Sec-WebSocket-Extensions") {
+                extensions = Some(match extensions {
+                    Some(extensions) => format!("{extensions}, {}", value.trim()),
+                    None => value.trim().to_string()
+                });
+            }
+        }
+    }
+
+    if !websocket {
+        return Err(Error::websocket(WebsocketError::UpgradeTypeError, None));
+    }
+    if !upgrade {
+        return Err(Error::websocket(WebsocketError::UpgradeConncetionError, None));
+    }
+    if !accept {
+        return Err(Error::websocket(WebsocketError::UpgradeAcceptError, None));
+    }
+    if !protocol {
+        return Err(Error::websocket(WebsocketError::UpgradeProtocolError, None));
+    }
+
+    Ok(extensions)
+}
+
+pub(crate) fn new_sec_websocket_key() -> String {
+    let mut random =  [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut random);
+    general_purpose::STANDARD.encode(random)
+}
+
+///Random UUID (version 4) to identify a request
+pub(crate) fn new_request_id() -> String {
+    let mut random = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut random);
+    random[6] = (random[6] & 0x0f) | 0x40;
+    random[8] = (random[8] & 0x3f) | 0x80;
+
+    let hex: String = random.iter().map(|byte| format!("{byte:02x}")).collect();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+fn check_sec_websocket_accept(sec_websocket_accept: String, sec_websocket_key: String) -> bool {
+    //recreate supposed sec_websocket_accept value
+    let fixed = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11".to_string();
+    let mut check_value = format!("{}{}", sec_websocket_key, fixed);
+    let mut hasher = Sha1::new();
+    hasher.update(check_value);
+    check_value = general_purpose::STANDARD.encode(hasher.finalize());
+
+    sec_websocket_accept == check_value
+}
+
+fn opcode1_payload(rpcversion: usize, password: Option<String>, event_subscriptions: OBSEventSubscription, opcode0_message: Payload) -> Result<Value, Error> {
+    let data = opcode0_message.data();
+
+    let requested_rpcversion = data["rpcVersion"].as_u64();
+    if requested_rpcversion != Some(rpcversion as u64) {
+        return Err(Error::websocket(WebsocketError::RpcVersionNotSupported, Some(format!("requested {}, supported {rpcversion}", data["rpcVersion"]))));
+    }
+
+    //authentication is only sent by the server if it is required
+    if let Some(authentication) = data.get("authentication") {
+        let challenge = authentication["challenge"].as_str().unwrap_or_default().to_string();
+        let salt = authentication["salt"].as_str().unwrap_or_default().to_string();
+        let authentication = match password {
+            Some(password) => create_authentication_string(password, challenge, salt),
+            None => return Err(Error::websocket(WebsocketError::AuthenticationRequired, None))
+        };
+        Ok(Payload::opcode_identify_message(rpcversion, Some(authentication), event_subscriptions))
+    }
+    else {
+        Ok(Payload::opcode_identify_message(rpcversion, None, event_subscriptions))
+    }
+}
+
+fn create_authentication_string(password: String, challenge: String, salt: String) -> String {
+    let secret = create_base64_secret(format!("{password}{salt}"));
+    create_base64_secret(format!("{secret}{challenge}"))
+}
+
+fn create_base64_secret(text: String) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(text);
+    let hash = hasher.finalize();
+    general_purpose::STANDARD.encode(hash)
+}
@@ -1,10 +1,15 @@
+use std::fmt;
+
 pub struct Header {
     fin: bool,
+    ///RSV1, set on the first frame of a compressed message (permessage-deflate)
+    rsv1: bool,
     opcode: Opcode,
     masked: bool,
     payload_length: u64
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum Opcode {
     ContinuationFrame,
     TextFrame,
@@ -14,40 +19,64 @@ pub enum Opcode {
     Pong
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum HeaderError {
     InsufficientHeaderData,
     InsufficientPayloadLengthData,
-    CustomOpcodeNotSupported
+    CustomOpcodeNotSupported,
+    ReservedBitsSet
 }
 
 impl Header {
     pub fn new (fin: bool, opcode: Opcode, masked: bool, payload_length: u64) -> Header {
-        Header{fin, opcode, masked, payload_length}
+        Header{fin, rsv1: false, opcode, masked, payload_length}
+    }
+
+    ///true if the payload of this message is compressed
+    pub fn has_rsv1_set(&self) -> bool {
+        self.rsv1
+    }
+
+    pub fn set_rsv1(&mut self, rsv1: bool) {
+        self.rsv1 = rsv1;
     }
 
     pub fn get_value_payload_length(&self) -> u64 {
-        return self.payload_length.clone();
+        self.payload_length
+    }
+
+    pub fn get_value_opcode(&self) -> Opcode {
+        self.opcode
     }
 
     pub fn has_mask_byte_set(&self) -> bool {
-        return self.masked.clone();
+        self.masked
+    }
+
+    ///true if this frame is the last fragment of a message
+    pub fn has_fin_byte_set(&self) -> bool {
+        self.fin
+    }
+
+    ///Control frames (close, ping, pong) may be sent in the middle of a fragmented message
+    pub fn is_control_frame(&self) -> bool {
+        matches!(self.opcode, Opcode::ConnectionClose | Opcode::Ping | Opcode::Pong)
     }
 
     pub fn from_bytes(bytes: Vec<u8>) -> Result<Header, HeaderError> {
-        match Header::check_bytes(&bytes) {
-            Ok(()) => (),
-            Err(error) => return Err(error)
-        };
+        Header::check_bytes(&bytes)?;
 
         let fin = bytes[0] >= 128;
-        let opcode = match Header::opcode_match_enum(bytes[0]) {
-            Ok(value) => value,
-            Err (error) => return Err(error)
-        };
+        let rsv1 = bytes[0] & 0x40 != 0;
+        //RSV2 and RSV3 belong to extensions that are never negotiated
+        if bytes[0] & 0x30 != 0 {
+            return Err(HeaderError::ReservedBitsSet);
+        }
+        let opcode = Header::opcode_match_enum(bytes[0])?;
         let masked = bytes[1] >= 128;
         let payload_length = Header::get_payload_length_from_message(&bytes);
 
-        Ok(Header{fin, opcode, masked, payload_length})
+        Ok(Header{fin, rsv1, opcode, masked, payload_length})
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
@@ -56,6 +85,9 @@ impl Header {
         if self.fin {
             byte1 += 128;
         }
+        if self.rsv1 {
+            byte1 += 64;
+        }
         byte1 += Header::opcode_match_value(&self.opcode);
         bheader.push(byte1);
 
@@ -83,25 +115,25 @@ impl Header {
     }
 
     ///check if vector of bytes fulfills all criteria to be correctly interpreted as a WebSocket header
-    fn check_bytes(bytes: &Vec<u8>) -> Result<(), HeaderError> {
+    fn check_bytes(bytes: &[u8]) -> Result<(), HeaderError> {
         //check if vector has minimum header size
         if bytes.len() >= 2 {
             let minimum_header: [u8; 2] = [bytes[0], bytes[1]];
 
             //check if vector has required header size
             if bytes.len() >= Header::required_header_size(minimum_header) {
-                return Ok(());
+                Ok(())
             }
             else {
-                return Err(HeaderError::InsufficientPayloadLengthData)
+                Err(HeaderError::InsufficientPayloadLengthData)
             }
         }
         else {
-            return Err(HeaderError::InsufficientHeaderData);
+            Err(HeaderError::InsufficientHeaderData)
         }
     }
 
-    pub fn get_payload_length_from_message (bytes: &Vec<u8>) -> u64 {
+    pub fn get_payload_length_from_message (bytes: &[u8]) -> u64 {
         let minimum_header: [u8; 2] = [bytes[0], bytes[1]];
         let header_size = Header::required_header_size(minimum_header);
         let mut payload_length: u64 = 0;
@@ -122,16 +154,14 @@ impl Header {
 
     ///Returns required header size in bytes, calculated from the first 2 bytes of the header
     pub fn required_header_size (bytes: [u8; 2]) -> usize {
-        let mut byte = bytes[1].clone();
-
         //remove Mask bit
-        byte = byte % 128;
+        let byte = bytes[1] % 128;
 
         //match required header size in byte
         match byte {
             126 => 4,
             127 => 10,
-            other => 2
+            _ => 2
         }
     }
 
@@ -150,7 +180,7 @@ impl Header {
     /// Get Opcode enum from Value
     /// Accepts full first 8 bits from Websocket header
     pub fn opcode_match_enum(mut opcode: u8) -> Result<Opcode, HeaderError> {
-        opcode = opcode % 16;
+        opcode %= 16;
         match opcode {
             0 => Ok(Opcode::ContinuationFrame),
             1 => Ok(Opcode::TextFrame),
@@ -162,12 +192,16 @@ impl Header {
         }
     }
 
-    pub fn error_strings(error: HeaderError) -> String {
-        match error {
-            HeaderError::CustomOpcodeNotSupported => "Custom operation codes are not supported by this program".to_string(),
-            HeaderError::InsufficientHeaderData => "Insufficient data to parse websocket header (minimum 2 bytes)".to_string(),
-            HeaderError::InsufficientPayloadLengthData => "Insufficient header data to read payload length".to_string()
-        }
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HeaderError::CustomOpcodeNotSupported => "Custom operation codes are not supported by this program",
+            HeaderError::InsufficientHeaderData => "Insufficient data to parse websocket header (minimum 2 bytes)",
+            HeaderError::InsufficientPayloadLengthData => "Insufficient header data to read payload length",
+            HeaderError::ReservedBitsSet => "Reserved bits RSV2 or RSV3 are set without a negotiated extension"
+        })
     }
 }
 
@@ -0,0 +1,61 @@
+use std::time::{Duration, Instant};
+
+///Keeps track of the pings sent to and the pongs recieved from the WebSocket server
+#[derive(Clone, Debug)]
+pub struct Heartbeat {
+    last_ping: Option<Instant>,
+    last_pong: Option<Instant>,
+    round_trip: Option<Duration>
+}
+
+impl Heartbeat {
+    pub fn new() -> Heartbeat {
+        Heartbeat{last_ping: None, last_pong: None, round_trip: None}
+    }
+
+    ///Point in time of the last ping sent by this client
+    pub fn last_ping(&self) -> Option<Instant> {
+        self.last_ping
+    }
+
+    ///Point in time of the last pong recieved from the server
+    pub fn last_pong(&self) -> Option<Instant> {
+        self.last_pong
+    }
+
+    ///Time between the last ping and the pong answering it
+    pub fn round_trip(&self) -> Option<Duration> {
+        self.round_trip
+    }
+
+    ///true if a ping was sent and no pong arrived within the given timeout
+    pub fn is_expired(&self, timeout: Duration) -> bool {
+        match (self.last_ping, self.last_pong) {
+            (Some(ping), Some(pong)) if pong >= ping => false,
+            (Some(ping), _) => ping.elapsed() > timeout,
+            (None, _) => false
+        }
+    }
+
+    pub fn ping_sent(&mut self) {
+        self.last_ping = Some(Instant::now());
+    }
+
+    pub fn pong_recieved(&mut self) {
+        let now = Instant::now();
+        //only the first pong after a ping answers it
+        if let Some(ping) = self.last_ping {
+            match self.last_pong {
+                Some(pong) if pong >= ping => (),
+                _ => self.round_trip = Some(now.duration_since(ping))
+            }
+        }
+        self.last_pong = Some(now);
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Heartbeat::new()
+    }
+}
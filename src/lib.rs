@@ -1,195 +1,139 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
 use websocket::Websocket;
 
-use crate::message::Message;
-use crate::payload::Payload;
+use crate::batch::RequestBatch;
+use crate::error::Error;
+use crate::obs::OBSRequestBatchExecutionType;
+use crate::requests::*;
 
+mod codec;
 mod message;
 mod header;
-mod payload;
-mod obs;
+mod handshake;
+mod stream;
+#[cfg(feature = "async")]
+pub mod async_websocket;
+pub mod batch;
+pub mod error;
+pub mod payload;
+pub mod obs;
+pub mod request_status;
+pub mod requests;
+pub mod close_frame;
+pub mod connect_options;
+pub mod deflate;
+pub mod encoding;
+pub mod event;
+pub mod heartbeat;
+pub mod reconnecting_websocket;
+pub mod shared_websocket;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod url;
 pub mod websocket;
 
-pub fn toggle_scene_item(websocket: &mut Websocket, scene: &String, scene_item: &String) -> Result<(), String> {
+//a panic while holding one of the locks does not leave the data inconsistent
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub fn toggle_scene_item(websocket: &mut Websocket, scene: &str, scene_item: &str) -> Result<(), Error> {
     //get id
-    let id = match scene_item_id(websocket, &scene, &scene_item) {
-        Ok(id) => id,
-        Err(error) => return Err(error)
-    };
+    let id = scene_item_id(websocket, scene, scene_item)?;
 
     //toggle
-    let show = match scene_item_enabled(websocket, &scene, &id) {
-        Ok(enabled) => !enabled, 
-        Err(error) => return Err(error)
-    };
-    set_scene_item_show_status(websocket, &scene, &id, show)
+    let show = !scene_item_enabled(websocket, scene, id)?;
+    set_scene_item_show_status(websocket, scene, id, show)
 }
 
-pub fn set_scene_item_status(websocket: &mut Websocket, scene: &String, scene_item: &String, show: bool) -> Result<(), String> {
+pub fn set_scene_item_status(websocket: &mut Websocket, scene: &str, scene_item: &str, show: bool) -> Result<(), Error> {
     //get id
-    let id = match scene_item_id(websocket, &scene, &scene_item) {
-        Ok(id) => id,
-        Err(error) => return Err(error)
-    };
+    let id = scene_item_id(websocket, scene, scene_item)?;
 
     //get current scene item status
-    let enabled = match scene_item_enabled(websocket, &scene, &id) {
-        Ok(enabled) => enabled, 
-        Err(error) => return Err(error)
-    };
+    let enabled = scene_item_enabled(websocket, scene, id)?;
 
     if enabled != show {
-        set_scene_item_show_status(websocket, &scene, &id, show)
+        set_scene_item_show_status(websocket, scene, id, show)
     }
     else {
         Ok(())
     }
 }
 
-pub fn show_scene(websocket: &mut Websocket, scene: &String) -> Result<(), String> {
-    let mut payload = "{".to_string();
-    payload = payload + "\"op\":6,";
-    payload = payload + "\"d\":{";
-    payload = payload + "\"requestType\":\"SetCurrentProgramScene\",";
-    payload = payload + "\"requestId\":\"f819dcf0-89cc-11eb-8f0e-382c4ac93b9c\",";
-    payload = payload + "\"requestData\":{";
-    payload = format!("{payload}\"sceneName\":\"{scene}\"");
-    payload = payload + "}}}";
-    println!("{payload}");
-
-    println!("{payload}");
-
-    let _answer = match send_and_recieve(websocket, payload) {
-        Ok(answer) => answer,
-        Err(error) => return Err(error)
-    };
-
-    Ok(())
-}
-
-fn set_scene_item_show_status(websocket: &mut Websocket, scene: &String, id: &usize, show: bool) -> Result<(), String> {
-    let mut payload = "{".to_string();
-    payload = payload + "\"op\":6,";
-    payload = payload + "\"d\":{";
-    payload = payload + "\"requestType\":\"SetSceneItemEnabled\",";
-    payload = payload + "\"requestId\":\"f819dcf0-89cc-11eb-8f0e-382c4ac93b9c\",";
-    payload = payload + "\"requestData\":{";
-    payload = format!("{payload}\"sceneName\":\"{scene}\",");
-    payload = format!("{payload}\"sceneItemId\":{id},");
-    if show {
-        payload = payload + "\"sceneItemEnabled\":true";
+///Shows one scene item and hides the others. All changes become visible in the same frame.
+pub fn show_one_scene_item(websocket: &mut Websocket, scene: &str, scene_item: &str, hide: &[String]) -> Result<(), Error> {
+    //get ids
+    let mut id_batch = RequestBatch::new(OBSRequestBatchExecutionType::SerialRealtime, false);
+    for item in std::iter::once(scene_item).chain(hide.iter().map(String::as_str)) {
+        id_batch.add_request(&GetSceneItemId {
+            scene_name: Some(scene.to_string()),
+            source_name: item.to_string(),
+            ..Default::default()
+        })?;
     }
-    else {
-        payload = payload + "\"sceneItemEnabled\":false";
+    let mut ids: Vec<i64> = Vec::new();
+    for result in websocket.request_batch(&id_batch)? {
+        let response: GetSceneItemIdResponse = result.check_request_status()?.typed_response()?;
+        ids.push(response.scene_item_id);
     }
-    payload = payload + "}}}";
-    println!("{payload}");
 
-    let _answer = match send_and_recieve(websocket, payload) {
-        Ok(answer) => answer,
-        Err(error) => return Err(error)
-    };
+    //show first, hide the others
+    let mut batch = RequestBatch::new(OBSRequestBatchExecutionType::SerialFrame, true);
+    for (index, id) in ids.iter().enumerate() {
+        batch.add_request(&SetSceneItemEnabled {
+            scene_name: Some(scene.to_string()),
+            scene_item_id: *id,
+            scene_item_enabled: index == 0,
+            ..Default::default()
+        })?;
+    }
+    for result in websocket.request_batch(&batch)? {
+        result.check_request_status()?;
+    }
 
     Ok(())
 }
 
-fn scene_item_enabled(websocket: &mut Websocket, scene: &String, id: &usize) -> Result<bool, String> {
-    let mut payload = "{".to_string();
-    payload = payload + "\"op\":6,";
-    payload = payload + "\"d\":{";
-    payload = payload + "\"requestType\":\"GetSceneItemEnabled\",";
-    payload = payload + "\"requestId\":\"f819dcf0-89cc-11eb-8f0e-382c4ac93b9c\",";
-    payload = payload + "\"requestData\":{";
-    payload = format!("{payload}\"sceneName\":\"{scene}\",");
-    payload = format!("{payload}\"sceneItemId\":{id}");
-    payload = payload + "}}}";
-    println!("{payload}");
-    
-    let answer = match send_and_recieve(websocket, payload) {
-        Ok(answer) => answer,
-        Err(error) => return Err(error)
-    };
-    
-    if answer.payload().contains("\"sceneItemEnabled\":") {
-        Ok(answer.payload().contains("\"sceneItemEnabled\":true"))
-    }
-    else {
-        Err("Could not get status of scene item. Make sure it exists".to_string())
-    }
-    
+pub fn show_scene(websocket: &mut Websocket, scene: &str) -> Result<(), Error> {
+    websocket.call(&SetCurrentProgramScene {
+        scene_name: Some(scene.to_string()),
+        ..Default::default()
+    })
 }
 
-fn scene_item_id(websocket: &mut Websocket, scene: &String, scene_item: &String) -> Result<usize, String> {
-    let mut payload = "{".to_string();
-    payload = payload + "\"op\":6,";
-    payload = payload + "\"d\":{";
-    payload = payload + "\"requestType\":\"GetSceneItemId\",";
-    payload = payload + "\"requestId\":\"f819dcf0-89cc-11eb-8f0e-382c4ac93b9c\",";
-    payload = payload + "\"requestData\":{";
-    payload = format!("{payload}\"sceneName\":\"{scene}\",");
-    payload = format!("{payload}\"sourceName\":\"{scene_item}\"");
-    payload = payload + "}}}";
-    println!("{payload}");
-
-    let answer = match send_and_recieve(websocket, payload) {
-        Ok(answer) => answer,
-        Err(error) => return Err(error)
-    };
-    let payload = match Payload::from_string(answer.payload_value()) {
-        Ok(payload) => payload,
-        Err(error) => return Err(error)
-    };
-
-    let mut id_str = String::new();
-    let mut id: usize = 0;
-    for (key, value) in payload.attributes() {
-        match key.as_str() {
-            "sceneItemId" => {id_str = value},
-            _ => ()
-        }
-    }
-    if !id_str.is_empty() {
-        id = match id_str.parse() {
-            Ok(id) => id,
-            Err(_) => return Err("Could not parse id of scene item (not a number)".to_string())
-        }
-    }
-    else {
-        return Err("id of scene item not found".to_string());
-    }
-    Ok(id)
+fn set_scene_item_show_status(websocket: &mut Websocket, scene: &str, id: i64, show: bool) -> Result<(), Error> {
+    websocket.call(&SetSceneItemEnabled {
+        scene_name: Some(scene.to_string()),
+        scene_item_id: id,
+        scene_item_enabled: show,
+        ..Default::default()
+    })
 }
 
-pub fn create_record_chapter(websocket: &mut Websocket) -> Result<(), String> {
-    let mut payload = "{".to_string();
-    payload = payload + "\"op\":6,";
-    payload = payload + "\"d\":{";
-    payload = payload + "\"requestType\":\"CreateRecordChapter\",";
-    payload = payload + "\"requestId\":\"f819dcf0-89cc-11eb-8f0e-382c4ac93b9c\",";
-    payload = payload + "\"requestData\":{";
-    payload = format!("{payload}\"chapterName\":\"marker\"");
-    payload = payload + "}}}";
-    println!("{payload}");
-
-    let _answer = match send_and_recieve(websocket, payload) {
-        Ok(answer) => answer,
-        Err(error) => return Err(error)
-    };
+fn scene_item_enabled(websocket: &mut Websocket, scene: &str, id: i64) -> Result<bool, Error> {
+    let response = websocket.call(&GetSceneItemEnabled {
+        scene_name: Some(scene.to_string()),
+        scene_item_id: id,
+        ..Default::default()
+    })?;
 
-    Ok(())
+    Ok(response.scene_item_enabled)
 }
 
-fn send_and_recieve(websocket: &mut Websocket, payload: String) -> Result<Message, String>{
-    let message = Message::new(true, crate::header::Opcode::TextFrame, true, payload);
-    
-    match websocket.send_message(message) {
-        Ok(()) => (),
-        Err(error) => return Err(error)
-    }
-    let answer = match websocket.read_message() {
-        Ok(answer) => answer,
-        Err(error) => return Err(error)
-    };
-    println!("{}", answer.payload());
-
-    Ok(answer)
-}
\ No newline at end of file
+fn scene_item_id(websocket: &mut Websocket, scene: &str, scene_item: &str) -> Result<i64, Error> {
+    let response = websocket.call(&GetSceneItemId {
+        scene_name: Some(scene.to_string()),
+        source_name: scene_item.to_string(),
+        ..Default::default()
+    })?;
+
+    Ok(response.scene_item_id)
+}
+
+pub fn create_record_chapter(websocket: &mut Websocket) -> Result<(), Error> {
+    websocket.call(&CreateRecordChapter {
+        chapter_name: Some("marker".to_string())
+    })
+}
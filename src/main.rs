@@ -1,22 +1,70 @@
-use std::env;
-use std::net::SocketAddr;
+use std::{env, thread};
 
-use obs_remote_1lt::websocket::Websocket;
+use log::LevelFilter;
 
+use obs_remote_1lt::connect_options::ConnectOptions;
+use obs_remote_1lt::deflate::DeflateConfig;
+use obs_remote_1lt::obs::OBSEventSubscription;
+use obs_remote_1lt::websocket::{Websocket, FRAME_LOG_TARGET};
 
+use crate::config::{CommandLine, ConfigFile, Connection, ConnectionSettings};
+
+mod config;
+
+///Connection settings are taken from the command line options, then the selected profile, then the environment,
+///then the top level settings of the config file, each value from the first source that sets it (see config.rs).
+///With several profiles the command runs on all of them at the same time and the result of each is reported.
 fn main() -> Result<(), String>{
-    let rpcversion = 1;
-    
-    let mut websocket = match Websocket::new(SocketAddr::from(([127, 0, 0, 1], 4455)), Some(format!("46Y6AzmrmHDoT5fz")), rpcversion) {
-        Ok(websocket) => websocket,
-        Err(e) =>  { 
-            println!("Error while connecting to OBS: {}", e);
-            return Err(format!("Program shutdown because the connection to OBS Websocket could not be established"));
+    let command_line = CommandLine::parse(env::args())?;
+    init_logging(command_line.verbose, command_line.trace_frames);
+
+    let config = ConfigFile::load(command_line.config.as_deref())?;
+    let environment = ConnectionSettings::from_env()?.normalized()?;
+    let profiles = config.selected_profiles(&command_line);
+
+    if profiles.len() <= 1 {
+        let connection = config.connection(&command_line, &environment, profiles.first().map(String::as_str))?;
+        return run(connection, command_line.args);
+    }
+
+    let results: Vec<(&String, Result<(), String>)> = thread::scope(|scope| {
+        let handles: Vec<_> = profiles.iter().map(|profile| {
+            let connection = config.connection(&command_line, &environment, Some(profile));
+            let args = command_line.args.clone();
+            (profile, scope.spawn(move || run(connection?, args)))
+        }).collect();
+
+        handles.into_iter()
+            .map(|(profile, handle)| (profile, handle.join().unwrap_or_else(|_| Err("panicked".to_string()))))
+            .collect()
+    });
+
+    let mut failed = 0;
+    for (profile, result) in &results {
+        match result {
+            Ok(()) => println!("{profile}: ok"),
+            Err(error) => {
+                failed += 1;
+                println!("{profile}: failed: {error}");
+            }
         }
+    }
+    if failed > 0 {
+        Err(format!("{failed} of {} profiles failed", results.len()))
+    }
+    else {
+        Ok(())
+    }
+}
+
+///Connects to one OBS instance and runs the command
+fn run(connection: Connection, args: Vec<String>) -> Result<(), String> {
+    let url = connection.url();
+    let websocket = match connect(&connection) {
+        Ok(websocket) => websocket,
+        Err(e) => return Err(format!("Could not connect to OBS at {url}: {e}"))
     };
 
-    let args: Vec<String> = env::args().collect();
-    dbg!(&args);
     if args.len() > 1 {
         match args[1].as_str() {
             "toggle" => toggle_scene_item(websocket, args),
@@ -31,16 +79,35 @@ fn main() -> Result<(), String>{
     }
 }
 
+fn connect(connection: &Connection) -> Result<Websocket, String> {
+    let mut options = ConnectOptions::new().encoding(connection.encoding);
+    if connection.compress {
+        options = options.deflate(DeflateConfig::default());
+    }
+    #[cfg(feature = "tls")]
+    if connection.tls {
+        options = options.tls(connection.tls_config()?);
+    }
+    #[cfg(not(feature = "tls"))]
+    if connection.ca_file.is_some() || connection.pin_sha256.is_some() {
+        return Err("ca_file and pin_sha256 need the tls feature".to_string());
+    }
+
+    Websocket::connect_with(connection.url(), &options, connection.password.clone(), connection.rpc_version, OBSEventSubscription::None)
+        .map_err(|error| error.to_string())
+}
+
 fn toggle_scene_item(mut websocket: Websocket, args: Vec<String>) -> Result<(), String> {
     if args.len() >= 4 {
         obs_remote_1lt::toggle_scene_item(&mut websocket, &args[2], &args[3])
+            .map_err(|error| error.to_string())
     }
     else {
         if args.len() == 2 {
-            return Err("Missing arguments scene and scene item to toggle".to_string())
+            Err("Missing arguments scene and scene item to toggle".to_string())
         }
         else {
-            return Err("Missing argument scene item to toggle".to_string())
+            Err("Missing argument scene item to toggle".to_string())
         }   
     }
 }
@@ -48,45 +115,48 @@ fn toggle_scene_item(mut websocket: Websocket, args: Vec<String>) -> Result<(),
 fn show_scene(mut websocket: Websocket, args: Vec<String>) -> Result<(), String> {
     if args.len() >= 3 {
         obs_remote_1lt::show_scene(&mut websocket, &args[2])
+            .map_err(|error| error.to_string())
     }
     else {
-        return Err("Missing argument scene to show".to_string())
+        Err("Missing argument scene to show".to_string())
     }
 }
 
 fn show_one_scene_item(mut websocket: Websocket, args: Vec<String>) -> Result<(), String> {
     if args.len() >= 5 {
-        match obs_remote_1lt::set_scene_item_status(&mut websocket, &args[2], &args[3], true) {
-            Ok(()) => (),
-            Err(error) => return Err(error)
-        }
-
-        for scene_item in &args[4..] {
-            match obs_remote_1lt::set_scene_item_status(&mut websocket, &args[2], scene_item, false) {
-                Ok(()) => (),
-                Err(error) => return Err(error)
-            }
-        }
+        obs_remote_1lt::show_one_scene_item(&mut websocket, &args[2], &args[3], &args[4..])
+            .map_err(|error| error.to_string())
+    }
+    else if args.len() == 2 {
+        Err("Missing arguments scene, scene item to toggle and scene item to hide".to_string())
+    }
+    else if args.len() == 3 {
+        Err("Missing argument scene item to show and scene item to hide".to_string())
     }
     else {
-        if args.len() == 2 {
-            return Err("Missing arguments scene, scene item to toggle and scene item to hide".to_string())
-        }
-        else if args.len() == 3 {
-            return Err("Missing argument scene item to show and scene item to hide".to_string())
-        }
-        else {
-            return Err("Missing argument scene item to hide".to_string())
-        }
+        Err("Missing argument scene item to hide".to_string())
     }
-
-
-    Ok(())
 }
 
 fn set_chapter(mut websocket: Websocket) -> Result<(), String> {
     match obs_remote_1lt::create_record_chapter(&mut websocket) {
         Ok(()) => Ok(()),
-        Err(error) => Err(error)
+        Err(error) => Err(error.to_string())
     }
-}
\ No newline at end of file
+}
+
+///Logs go to stderr so the output of the commands stays usable in scripts.
+///-v shows what the program does, --trace-frames additionally logs every WebSocket frame (authentication is redacted).
+///RUST_LOG overrides both (e.g. RUST_LOG=obs_remote_1lt=debug).
+fn init_logging(verbose: bool, trace_frames: bool) {
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(LevelFilter::Warn);
+    if verbose {
+        builder.filter_module("obs_remote_1lt", LevelFilter::Debug);
+    }
+    if trace_frames {
+        builder.filter_module(FRAME_LOG_TARGET, LevelFilter::Trace);
+    }
+    builder.parse_default_env();
+    builder.init();
+}
@@ -1,21 +1,30 @@
+use std::fmt;
+
 use rand::RngCore;
 
-use crate::header::{Header, Opcode};
+use crate::{error::Error, header::{Header, Opcode}};
 
 pub struct Message {
     header: Header,
     mask: Option<[u8; 4]>,
-    payload: String
+    payload: Vec<u8>
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum MessageError {
     InsufficientDataMask,
-    InsufficientDataPayload
+    InsufficientDataPayload,
+    InvalidUtf8
 }
 
 impl Message {
     pub fn new(fin: bool, opcode: Opcode, masked: bool, payload: String) -> Message {
-        let header = Header::new(fin, opcode, masked, payload.as_bytes().len() as u64);
+        Message::with_bytes(fin, opcode, masked, payload.into_bytes())
+    }
+
+    ///Create a message with a payload that is not text, e.g. the status code of a close frame
+    pub fn with_bytes(fin: bool, opcode: Opcode, masked: bool, payload: Vec<u8>) -> Message {
+        let header = Header::new(fin, opcode, masked, payload.len() as u64);
         let mask = if masked {Some(Message::create_random_mask())} else {None};
         Message{header, mask, payload}
     }
@@ -24,49 +33,67 @@ impl Message {
         &self.header
     }
 
-    pub fn payload(&self) -> &String {
+    ///Replaces the payload, e.g. with its compressed or decompressed form. rsv1 marks a compressed payload.
+    pub fn replace_payload(&mut self, payload: Vec<u8>, rsv1: bool) {
+        let mut header = Header::new(self.header.has_fin_byte_set(), self.header.get_value_opcode(), self.mask.is_some(), payload.len() as u64);
+        header.set_rsv1(rsv1);
+        self.header = header;
+        self.payload = payload;
+    }
+
+    ///Payload as text. Text messages are checked to be valid UTF-8 when they are read (see check_utf8),
+    ///binary payloads should be read with payload_bytes.
+    pub fn payload(&self) -> String {
+        String::from_utf8_lossy(&self.payload).to_string()
+    }
+
+    pub fn payload_bytes(&self) -> &[u8] {
         &self.payload
     }
 
-    pub fn from_bytes (bytes: Vec<u8>) -> Result<Message, String> {
+    ///The payload of a text message has to be valid UTF-8 (RFC 6455 section 5.6).
+    ///Fragmented messages can split characters between frames, so this is checked on the complete message.
+    pub fn check_utf8(&self) -> Result<(), MessageError> {
+        if self.header.get_value_opcode() == Opcode::TextFrame && std::str::from_utf8(&self.payload).is_err() {
+            Err(MessageError::InvalidUtf8)
+        }
+        else {
+            Ok(())
+        }
+    }
+
+    pub fn from_bytes (bytes: Vec<u8>) -> Result<Message, Error> {
         //get header
-        let header = match Header::from_bytes(bytes.clone()) {
-            Ok(h) => h,
-            Err(e) => return Err(Header::error_strings(e)) 
-        };
+        let header = Header::from_bytes(bytes.clone())?;
         //get header length for bytes to skip when getting payload
         let mut skip_bytes = Header::required_header_size([bytes[0], bytes[1]]);
         
         let mut mask: Option<[u8; 4]> = None;
         //get mask if set
         if header.has_mask_byte_set() {
-            mask = match Message::get_mask_from_bytes(bytes.clone(), skip_bytes.clone()) {
+            mask = match Message::get_mask_from_bytes(bytes.clone(), skip_bytes) {
                 Ok(mask) => Some(mask),
-                Err(e) => return Err(Message::error_strings(e))
+                Err(e) => return Err(e.into())
             };
             //add mask length to bytes to skip if set
             skip_bytes += 4;
         }
         
-        let payload = match Message::get_payload_string_from_bytes(bytes.clone(), skip_bytes, mask.clone()) {
-            Ok(payload) => payload,
-            Err(e) => Message::error_strings(e)
-        };
+        let payload = Message::get_payload_from_bytes(bytes, skip_bytes, mask)?;
 
         Ok(Message{header, mask, payload})
     }
 
     pub fn to_bytes (&self) -> Vec<u8> {
-        let payload = self.payload.as_bytes().to_vec();
+        let payload = self.payload.clone();
         let masked_payload = match self.mask {
             Some(mask) => Message::mask_payload(payload, mask),
             None => payload
         };
 
         let mut message: Vec<u8> = self.header.to_bytes();
-        match self.mask {
-            Some(mask) => message.extend_from_slice(&mask.to_vec()),
-            None => ()
+        if let Some(mask) = self.mask {
+            message.extend_from_slice(&mask);
         }
         message.extend_from_slice(&masked_payload);
 
@@ -74,15 +101,23 @@ impl Message {
     }
 
     pub fn payload_value(&self) -> String {
-        self.payload.clone()
+        self.payload()
+    }
+
+    ///Appends the payload of a continuation frame to this message.
+    ///The fin bit of the fragment is taken over, the opcode and RSV1 of the first frame are kept.
+    pub fn append_fragment(&mut self, fragment: Message) {
+        self.payload.extend(fragment.payload);
+        let mut header = Header::new(fragment.header.has_fin_byte_set(), self.header.get_value_opcode(), false, self.payload.len() as u64);
+        header.set_rsv1(self.header.has_rsv1_set());
+        self.header = header;
+        self.mask = None;
     }
 
     fn mask_payload(payload: Vec<u8>, mask: [u8; 4]) -> Vec<u8> {
         let mut masked_payload: Vec<u8> = Vec::new();
-        let mut index: usize = 0;
-        for byte in payload {
-            masked_payload.push(byte ^ mask[(index % 4) as usize]);
-            index += 1;
+        for (index, byte) in payload.into_iter().enumerate() {
+            masked_payload.push(byte ^ mask[index % 4]);
         }
         masked_payload
     }
@@ -97,22 +132,14 @@ impl Message {
         }
     }
 
-    fn get_payload_string_from_bytes(bytes: Vec<u8>, skip: usize, mask: Option<[u8; 4]>) -> Result<String, MessageError> {
+    fn get_payload_from_bytes(bytes: Vec<u8>, skip: usize, mask: Option<[u8; 4]>) -> Result<Vec<u8>, MessageError> {
         if bytes.len() >= skip {
-            let mut chars: Vec<char> = Vec::new();
-
-            let mut index: u64 = 0;
-            for byte in bytes[skip..].to_vec() {
-                let unmasked_byte = match mask {
-                    Some(mask) => byte ^ mask[(index % 4) as usize],
-                    None => byte
-                };
-                
-                chars.push(unmasked_byte as char);
-                index += 1;
-            }
+            let payload = bytes[skip..].to_vec();
 
-            Ok(String::from_iter(chars))
+            match mask {
+                Some(mask) => Ok(Message::mask_payload(payload, mask)),
+                None => Ok(payload)
+            }
         }
         else {
             Err(MessageError::InsufficientDataPayload)
@@ -125,10 +152,14 @@ impl Message {
         mask
     }
 
-    pub fn error_strings(error: MessageError) -> String {
-        match error {
-            MessageError::InsufficientDataMask => "Insufficient data to parse the mask of this message".to_string(),
-            MessageError::InsufficientDataPayload => "Insufficient data to parse the payload of this message".to_string()
-        }
+}
+
+impl fmt::Display for MessageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MessageError::InsufficientDataMask => "Insufficient data to parse the mask of this message",
+            MessageError::InsufficientDataPayload => "Insufficient data to parse the payload of this message",
+            MessageError::InvalidUtf8 => "The payload of this text message is not valid UTF-8"
+        })
     }
-}
\ No newline at end of file
+}
@@ -1,3 +1,6 @@
+use std::{fmt, ops::{BitAnd, BitOr, BitOrAssign, Sub}};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum OBSMatchError {
     OBSOpcodeNotFound,
     OBSWebSocketCloseCodeNotFound,
@@ -6,7 +9,7 @@ pub enum OBSMatchError {
     OBSEventSubscriptionNotFound,
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum OBSOpcode {
     Hello,
     Identify,
@@ -33,8 +36,8 @@ pub fn obs_opcode_match_value(obs_op_code: &OBSOpcode) -> String {
     }.to_string()
 }
 
-pub fn obs_opcode_match_enum(obs_op_code: &String) -> Result<OBSOpcode, String> {
-    Ok(match &obs_op_code[..] {
+pub fn obs_opcode_match_enum(obs_op_code: &str) -> Result<OBSOpcode, OBSMatchError> {
+    Ok(match obs_op_code {
         "0" => OBSOpcode::Hello,
         "1" => OBSOpcode::Identify,
         "2" => OBSOpcode::Identifyed,
@@ -44,11 +47,11 @@ pub fn obs_opcode_match_enum(obs_op_code: &String) -> Result<OBSOpcode, String>
         "7" => OBSOpcode::RequestResponse,
         "8" => OBSOpcode::RequestBatch,
         "9" => OBSOpcode::RequestBatchResponse,
-        _ => return Err(error_strings(OBSMatchError::OBSOpcodeNotFound, Some(obs_op_code.clone())))
+        _ => return Err(OBSMatchError::OBSOpcodeNotFound)
     })
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum OBSWebSocketCloseCode {
     DontClose,
     UnknownReason,
@@ -83,8 +86,8 @@ pub fn obs_websocket_close_code_match_value(obs_websocket_close_code: OBSWebSock
     }.to_string()
 }
 
-pub fn obs_websocket_close_code_match_enum(obs_websocket_close_code: &String) -> Result<OBSWebSocketCloseCode, OBSMatchError> {
-    Ok(match &obs_websocket_close_code[..] {
+pub fn obs_websocket_close_code_match_enum(obs_websocket_close_code: &str) -> Result<OBSWebSocketCloseCode, OBSMatchError> {
+    Ok(match obs_websocket_close_code {
         "0" => OBSWebSocketCloseCode::DontClose,
         "4000" => OBSWebSocketCloseCode::UnknownReason,
         "4002" => OBSWebSocketCloseCode::MessageDecodeError,
@@ -102,7 +105,7 @@ pub fn obs_websocket_close_code_match_enum(obs_websocket_close_code: &String) ->
     })
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum OBSRequestBatchExecutionType {
     None,
     SerialRealtime,
@@ -119,8 +122,8 @@ pub fn obs_request_batch_execution_type_match_value(obs_request_batch_execution_
     }.to_string()
 }
 
-pub fn obs_request_batch_execution_type_match_enum(obs_request_batch_execution_type: &String) -> Result<OBSRequestBatchExecutionType, OBSMatchError> {
-    Ok(match &obs_request_batch_execution_type[..] {
+pub fn obs_request_batch_execution_type_match_enum(obs_request_batch_execution_type: &str) -> Result<OBSRequestBatchExecutionType, OBSMatchError> {
+    Ok(match obs_request_batch_execution_type {
         "-1" => OBSRequestBatchExecutionType::None,
         "0" => OBSRequestBatchExecutionType::SerialRealtime,
         "1" => OBSRequestBatchExecutionType::SerialFrame,
@@ -129,7 +132,7 @@ pub fn obs_request_batch_execution_type_match_enum(obs_request_batch_execution_t
     })
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum OBSRequestStatus {
     Unknown,
     NoError,
@@ -246,83 +249,152 @@ pub fn obs_request_status_match_enum(obs_request_status: String) -> Result<OBSRe
     })
 }
 
-#[derive(Copy, Clone, PartialEq)]
-pub enum OBSEventSubscription {
-    None,
-    General,
-    Config,
-    Scenes,
-    Inputs,
-    Transitions,
-    Filters,
-    Outputs,
-    SceneItems,
-    MediaInputs,
-    Vendors,
-    Ui,
-    All,
-    InputVolumeMeters,
-    InputActiveStateChanged,
-    InputShowStateChanged,
-    SceneItemTransformChanged
+///Set of event categories a client subscribes to. Categories are combined with `|`:
+///`OBSEventSubscription::Scenes | OBSEventSubscription::Outputs`
+///
+///All does not contain the high-volume categories (InputVolumeMeters, InputActiveStateChanged,
+///InputShowStateChanged, SceneItemTransformChanged), they have to be requested explicitly.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, Default)]
+pub struct OBSEventSubscription(u32);
+
+#[allow(non_upper_case_globals)]
+impl OBSEventSubscription {
+    pub const None: OBSEventSubscription = OBSEventSubscription(0);
+    pub const General: OBSEventSubscription = OBSEventSubscription(1 << 0);
+    pub const Config: OBSEventSubscription = OBSEventSubscription(1 << 1);
+    pub const Scenes: OBSEventSubscription = OBSEventSubscription(1 << 2);
+    pub const Inputs: OBSEventSubscription = OBSEventSubscription(1 << 3);
+    pub const Transitions: OBSEventSubscription = OBSEventSubscription(1 << 4);
+    pub const Filters: OBSEventSubscription = OBSEventSubscription(1 << 5);
+    pub const Outputs: OBSEventSubscription = OBSEventSubscription(1 << 6);
+    pub const SceneItems: OBSEventSubscription = OBSEventSubscription(1 << 7);
+    pub const MediaInputs: OBSEventSubscription = OBSEventSubscription(1 << 8);
+    pub const Vendors: OBSEventSubscription = OBSEventSubscription(1 << 9);
+    pub const Ui: OBSEventSubscription = OBSEventSubscription(1 << 10);
+    pub const All: OBSEventSubscription = OBSEventSubscription((1 << 11) - 1);
+    pub const InputVolumeMeters: OBSEventSubscription = OBSEventSubscription(1 << 16);
+    pub const InputActiveStateChanged: OBSEventSubscription = OBSEventSubscription(1 << 17);
+    pub const InputShowStateChanged: OBSEventSubscription = OBSEventSubscription(1 << 18);
+    pub const SceneItemTransformChanged: OBSEventSubscription = OBSEventSubscription(1 << 19);
+
+    const KNOWN: u32 = OBSEventSubscription::All.0
+        | OBSEventSubscription::InputVolumeMeters.0
+        | OBSEventSubscription::InputActiveStateChanged.0
+        | OBSEventSubscription::InputShowStateChanged.0
+        | OBSEventSubscription::SceneItemTransformChanged.0;
+
+    ///Numeric value sent in the eventSubscriptions field of Identify and Reidentify
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub fn from_bits(bits: u32) -> Result<OBSEventSubscription, OBSMatchError> {
+        if bits & !OBSEventSubscription::KNOWN == 0 {
+            Ok(OBSEventSubscription(bits))
+        }
+        else {
+            Err(OBSMatchError::OBSEventSubscriptionNotFound)
+        }
+    }
+
+    ///true if every category of other is part of this set
+    pub fn contains(&self, other: OBSEventSubscription) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    ///true if at least one category of other is part of this set
+    pub fn intersects(&self, other: OBSEventSubscription) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn insert(&mut self, other: OBSEventSubscription) {
+        self.0 |= other.0;
+    }
+
+    pub fn remove(&mut self, other: OBSEventSubscription) {
+        self.0 &= !other.0;
+    }
+}
+
+impl BitOr for OBSEventSubscription {
+    type Output = OBSEventSubscription;
+
+    fn bitor(self, rhs: OBSEventSubscription) -> OBSEventSubscription {
+        OBSEventSubscription(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for OBSEventSubscription {
+    fn bitor_assign(&mut self, rhs: OBSEventSubscription) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for OBSEventSubscription {
+    type Output = OBSEventSubscription;
+
+    fn bitand(self, rhs: OBSEventSubscription) -> OBSEventSubscription {
+        OBSEventSubscription(self.0 & rhs.0)
+    }
+}
+
+impl Sub for OBSEventSubscription {
+    type Output = OBSEventSubscription;
+
+    fn sub(self, rhs: OBSEventSubscription) -> OBSEventSubscription {
+        OBSEventSubscription(self.0 & !rhs.0)
+    }
 }
 
 pub fn obs_event_subscription_match_value(obs_event_subscription: OBSEventSubscription) -> String {
-    match obs_event_subscription {
-        OBSEventSubscription::None => "0",
-        OBSEventSubscription::General => "(1 << 0)",
-        OBSEventSubscription::Config => "(1 << 1)",
-        OBSEventSubscription::Scenes => "(1 << 2)",
-        OBSEventSubscription::Inputs => "(1 << 3)",
-        OBSEventSubscription::Transitions => "(1 << 4)",
-        OBSEventSubscription::Filters => "(1 << 5)",
-        OBSEventSubscription::Outputs => "(1 << 6)",
-        OBSEventSubscription::SceneItems => "(1 << 7)",
-        OBSEventSubscription::MediaInputs => "(1 << 8)",
-        OBSEventSubscription::Vendors => "(1 << 9)",
-        OBSEventSubscription::Ui => "(1 << 10)",
-        OBSEventSubscription::All => "(General | Config | Scenes | Inputs | Transitions | Filters | Outputs | SceneItems | MediaInputs | Vendors | Ui)",
-        OBSEventSubscription::InputVolumeMeters => "(1 << 16)",
-        OBSEventSubscription::InputActiveStateChanged => "(1 << 17)",
-        OBSEventSubscription::InputShowStateChanged => "(1 << 18)",
-        OBSEventSubscription::SceneItemTransformChanged => "(1 << 19)"
-    }.to_string()
+    obs_event_subscription.bits().to_string()
 }
 
-pub fn obs_event_subscription_match_enum(obs_event_subscription: &String) -> Result<OBSEventSubscription, OBSMatchError> {
-    Ok(match &obs_event_subscription[..] {
-        "0" => OBSEventSubscription::None,
-        "(1 << 0)" => OBSEventSubscription::General,
-        "(1 << 1)" => OBSEventSubscription::Config,
-        "(1 << 2)" => OBSEventSubscription::Scenes,
-        "(1 << 3)" => OBSEventSubscription::Inputs,
-        "(1 << 4)" => OBSEventSubscription::Transitions,
-        "(1 << 5)" => OBSEventSubscription::Filters,
-        "(1 << 6)" => OBSEventSubscription::Outputs,
-        "(1 << 7)" => OBSEventSubscription::SceneItems,
-        "(1 << 8)" => OBSEventSubscription::MediaInputs,
-        "(1 << 9)" => OBSEventSubscription::Vendors,
-        "(1 << 10)" => OBSEventSubscription::Ui,
-        "(General | Config | Scenes | Inputs | Transitions | Filters | Outputs | SceneItems | MediaInputs | Vendors | Ui)" => OBSEventSubscription::All,
-        "(1 << 16)" => OBSEventSubscription::InputVolumeMeters,
-        "(1 << 17)" => OBSEventSubscription::InputActiveStateChanged,
-        "(1 << 18)" => OBSEventSubscription::InputShowStateChanged,
-        "(1 << 19)" => OBSEventSubscription::SceneItemTransformChanged,
-        _ => return Err(OBSMatchError::OBSEventSubscriptionNotFound)
-    })
+pub fn obs_event_subscription_match_enum(obs_event_subscription: &str) -> Result<OBSEventSubscription, OBSMatchError> {
+    match obs_event_subscription.parse() {
+        Ok(bits) => OBSEventSubscription::from_bits(bits),
+        Err(_) => Err(OBSMatchError::OBSEventSubscriptionNotFound)
+    }
+}
+
+impl fmt::Display for OBSMatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OBSMatchError::OBSOpcodeNotFound => "This OBS opcode does not exist",
+            OBSMatchError::OBSWebSocketCloseCodeNotFound => "This OBS WebSocket close code does not exist",
+            OBSMatchError::OBSRequestBatchExecutionTypeNotFound => "This OBS request batch execution type does not exist",
+            OBSMatchError::OBSRequestStatusNotFound => "This OBS reuquest status does not exist",
+            OBSMatchError::OBSEventSubscriptionNotFound => "This OBS event subscription does not exist"
+        })
+    }
 }
 
-fn error_strings(obs_match_error: OBSMatchError, information: Option<String>) -> String {
-    let mut message = match obs_match_error {
-        OBSMatchError::OBSOpcodeNotFound => "This OBS opcode does not exist",
-        OBSMatchError::OBSWebSocketCloseCodeNotFound => "This OBS WebSocket close code does not exist",
-        OBSMatchError::OBSRequestBatchExecutionTypeNotFound => "This OBS request batch execution type does not exist",
-        OBSMatchError::OBSRequestStatusNotFound => "This OBS reuquest status does not exist",
-        OBSMatchError::OBSEventSubscriptionNotFound => "This OBS event subscription does not exist"
-    }.to_string();
-
-    match information {
-        Some(information) => format!("{}: {}", message, information),
-        None => message
+#[cfg(test)]
+mod tests {
+    use super::OBSEventSubscription;
+
+    #[test]
+    fn event_subscription_bits_match_the_protocol() {
+        assert_eq!(OBSEventSubscription::General.bits(), 1);
+        assert_eq!(OBSEventSubscription::Ui.bits(), 1 << 10);
+        assert_eq!(OBSEventSubscription::All.bits(), 2047);
+        assert_eq!(OBSEventSubscription::InputVolumeMeters.bits(), 1 << 16);
+        assert_eq!(OBSEventSubscription::SceneItemTransformChanged.bits(), 1 << 19);
+
+        let high_volume = OBSEventSubscription::InputVolumeMeters
+            | OBSEventSubscription::InputActiveStateChanged
+            | OBSEventSubscription::InputShowStateChanged
+            | OBSEventSubscription::SceneItemTransformChanged;
+        assert!(!OBSEventSubscription::All.intersects(high_volume));
+
+        let subscription = OBSEventSubscription::Scenes | OBSEventSubscription::InputVolumeMeters;
+        assert_eq!(subscription.bits(), (1 << 2) | (1 << 16));
+        assert!(subscription.contains(OBSEventSubscription::Scenes));
+        assert_eq!(OBSEventSubscription::from_bits(subscription.bits()), Ok(subscription));
+        assert!(OBSEventSubscription::from_bits(1 << 11).is_err());
     }
-}
\ No newline at end of file
+}
@@ -1,37 +1,68 @@
-use std::{collections::HashMap};
+use std::fmt;
 
+use log::debug;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::batch::RequestBatch;
+use crate::error::Error;
+use crate::event::Event;
 use crate::obs::*;
+use crate::request_status::RequestStatus;
 
 pub struct Payload {
     opcode: OBSOpcode,
-    attributes: HashMap<String, String>
+    data: Value
+}
+
+///Where a message from OBS belongs, see Payload::route
+pub(crate) enum Route {
+    Event(Event),
+    ///Response to the request with the given request id
+    Response(String, Payload),
+    ///Any other message, e.g. an Identified answering Reidentify
+    Other(Payload)
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum PayloadError {
+    InvalidJson,
+    InvalidMessagePack,
     OpCodeNotFound,
-    ParseWrongNumberOfValues,
-    ParseEmptyValue,
-    MissingRequiredAttribute
+    DataNotFound,
+    MissingRequiredAttribute,
+    ClientMessage,
+    InvalidResponseData,
+    InvalidRequestData
 }
 
 impl Payload {
-    pub fn from_string(mut message: String) -> Result<Payload, String> {
-        message = Payload::clean_up_json(message);
-        let opcode = match Payload::get_opcode(message.clone()) {
-            Ok(opcode) => opcode,
-            Err(error) => return Err(error)
+    pub fn from_string(message: String) -> Result<Payload, Error> {
+        let json: Value = match serde_json::from_str(&message) {
+            Ok(json) => json,
+            Err(error) => return Err(Error::payload(PayloadError::InvalidJson, Some(format!("{error}: {message}"))))
+        };
+        Payload::from_value(json)
+    }
+
+    ///Parses a message that is already decoded, e.g. from MessagePack
+    pub fn from_value(mut json: Value) -> Result<Payload, Error> {
+        let opcode = Payload::get_opcode(&json)?;
+        let data = match json.get_mut("d") {
+            Some(data) if data.is_object() => data.take(),
+            _ => return Err(Error::payload(PayloadError::DataNotFound, Some(json.to_string())))
         };
 
         match opcode {
-            OBSOpcode::Hello => Payload::opcode_hello_from_string(message),
-            OBSOpcode::Identify => Payload::opcode_identify_from_string(message),
-            OBSOpcode::Identifyed => Payload::opcode_identified_from_string(message),
-            OBSOpcode::Reidentify => Payload::opcode_reidentify_from_string(message),
-            OBSOpcode::Event => Payload::opcode_event_from_string(message),
-            OBSOpcode::Request => Payload::opcode_request_from_string(message),
-            OBSOpcode::RequestResponse => Payload::opcode_request_response_from_string(message),
-            OBSOpcode::RequestBatch => Payload::opcode_request_batch_from_string(message),
-            OBSOpcode::RequestBatchResponse => Payload::opcode_request_batch_response_from_string(message)
+            OBSOpcode::Hello => Payload::opcode_hello_from_data(data),
+            OBSOpcode::Identify => Payload::opcode_identify_from_data(data),
+            OBSOpcode::Identifyed => Payload::opcode_identified_from_data(data),
+            OBSOpcode::Reidentify => Payload::opcode_reidentify_from_data(data),
+            OBSOpcode::Event => Payload::opcode_event_from_data(data),
+            OBSOpcode::Request => Payload::opcode_request_from_data(data),
+            OBSOpcode::RequestResponse => Payload::opcode_request_response_from_data(data),
+            OBSOpcode::RequestBatch => Payload::opcode_request_batch_from_data(data),
+            OBSOpcode::RequestBatchResponse => Payload::opcode_request_batch_response_from_data(data)
         }
     }
 
@@ -39,232 +70,341 @@ impl Payload {
         self.opcode
     }
 
-    pub fn attributes(&self) -> HashMap<String, String> {
-        self.attributes.clone()
-    }
-
-    fn clean_up_json(mut message: String) -> String {
-        message = message.replace("\"d\":", "");
-        message = message.replace("{", "");
-        message = message.replace("}", "");
-        message
+    ///Content of the "d" field of the message
+    pub fn data(&self) -> &Value {
+        &self.data
     }
 
-    fn get_opcode(message: String) -> Result<OBSOpcode, String> {
-        let attributes: Vec<&str> = message.split(",").collect();
-        let mut op_val = "".to_string();
-        for attribute in attributes {
-            if attribute.contains("\"op\":") {
-                op_val = attribute.replace("\"op\":", "");
-            }
+    ///Status of a RequestResponse, None for every other message
+    pub fn request_status(&self) -> Option<RequestStatus> {
+        if self.opcode != OBSOpcode::RequestResponse {
+            return None;
         }
-        if op_val.is_empty() {
-            return Err(Payload::error_strings(PayloadError::OpCodeNotFound, Some(message)));
-        }
-
-        obs_opcode_match_enum(&op_val)
+        RequestStatus::from_value(&self.data["requestStatus"])
     }
 
-    fn opcode_hello_from_string(mut message: String) -> Result<Payload, String> {
-        let mut auth = false;
-        if message.contains("\"authentication\":") {
-            auth = true;
-            message = message.replace("\"authentication\":", "");
+    ///Checks the status of a RequestResponse and turns a failed request into an error
+    pub fn check_request_status(self) -> Result<Payload, Error> {
+        match self.request_status() {
+            Some(status) if !status.result() => {
+                let request_type = self.data["requestType"].as_str().unwrap_or_default();
+                Err(Error::RequestFailed{request_type: request_type.to_string(), status})
+            },
+            _ => Ok(self)
         }
+    }
 
-        message = message.replace("\"op\":0", "");
+    ///Content of the "responseData" field of a RequestResponse, Null if OBS sent no data
+    pub fn response_data(&self) -> &Value {
+        &self.data["responseData"]
+    }
 
-        let attributes = match Payload::parse_attributes(message) {
-            Ok(attributes) => attributes,
-            Err(error) => return Err(error)
+    ///Parses the "responseData" of a RequestResponse into the response type of a typed request (see requests::Request).
+    ///OBS leaves out "responseData" for requests without response data (Response = ()), it is parsed as Null.
+    pub fn typed_response<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        let data = match self.data.get("responseData") {
+            Some(data) => data.clone(),
+            None => Value::Null
         };
+        serde_json::from_value(data.clone())
+            .map_err(|error| Error::payload(PayloadError::InvalidResponseData, Some(format!("{error}: {data}"))))
+    }
 
-        let opcode = OBSOpcode::Hello;
-        match Payload::check_opcode_hello_attributes(&attributes, auth) {
-            Ok(()) => Ok(Payload{opcode, attributes}),
-            Err(error) => Err(error)
+    fn get_opcode(json: &Value) -> Result<OBSOpcode, Error> {
+        match json.get("op").and_then(Value::as_u64) {
+            Some(op) => obs_opcode_match_enum(&op.to_string()).map_err(|error| Error::OBSMatch{kind: error, information: Some(op.to_string())}),
+            None => Err(Error::payload(PayloadError::OpCodeNotFound, Some(json.to_string())))
         }
     }
 
-    fn check_opcode_hello_attributes(attributes: &HashMap<String, String>, auth: bool) -> Result<(), String> {
-        let mut websocketVersion = false;
-        let mut rpcVersion = false;
-        let mut challenge = false;
-        let mut salt = false;
-
-        //if authentication is not required
-        if !auth { 
-            challenge = true;
-            salt = true;
+    fn opcode_hello_from_data(data: Value) -> Result<Payload, Error> {
+        let mut required = vec!["/obsWebSocketVersion", "/rpcVersion"];
+        //if authentication is required
+        if data.get("authentication").is_some() {
+            required.push("/authentication/challenge");
+            required.push("/authentication/salt");
         }
 
-        for (key, value) in attributes {
-            match key.as_str() {
-                "obsWebSocketVersion" => websocketVersion = !value.is_empty(),
-                "rpcVersion" => rpcVersion = !value.is_empty(),
-                "challenge" => challenge = !value.is_empty(),
-                "salt" => salt = !value.is_empty(),
-                _ => ()
-            }
+        Payload::check_required_attributes(&data, 0, &required)?;
+        Ok(Payload{opcode: OBSOpcode::Hello, data})
+    }
+
+    fn opcode_identify_from_data(_data: Value) -> Result<Payload, Error> {
+        Err(Error::payload(PayloadError::ClientMessage, Some("Identify".to_string())))
+    }
+
+    pub fn opcode_identify_message(rpcversion: usize, authentication: Option<String>, event_subscriptions: OBSEventSubscription) -> Value {
+        let mut data = json!({
+            "rpcVersion": rpcversion,
+            "eventSubscriptions": event_subscriptions.bits()
+        });
+        if let Some(auth) = authentication {
+            data["authentication"] = Value::String(auth);
         }
+        json!({"op": 1, "d": data})
+    }
 
-        if websocketVersion && rpcVersion && challenge && salt {
-            return Ok(())
+    ///Replaces the authentication string of an Identify message so the message can be logged
+    pub fn redact(message: &str) -> String {
+        match serde_json::from_str::<Value>(message) {
+            Ok(mut json) if json.pointer("/d/authentication").is_some_and(Value::is_string) => {
+                json["d"]["authentication"] = Value::String("<redacted>".to_string());
+                json.to_string()
+            },
+            _ => message.to_string()
         }
-        else {
-            let mut atts = "Opcode 0 missing ".to_string();
+    }
 
-            if !websocketVersion {
-                atts = format!("{atts} \"obsWebSocketVersion\" ");
-            }
-            if !rpcVersion {
-                atts = format!("{atts} \"rpcVersion\" ");
-            }
-            if !challenge {
-                atts = format!("{atts} \"challenge\" ");
-            }
-            if !salt {
-                atts = format!("{atts} \"salt\" ");
+    fn opcode_identified_from_data(data: Value) -> Result<Payload, Error> {
+        Payload::check_required_attributes(&data, 2, &["/negotiatedRpcVersion"])?;
+        Ok(Payload{opcode: OBSOpcode::Identifyed, data})
+    }
+
+    fn opcode_reidentify_from_data(data: Value) -> Result<Payload, Error> {
+        Ok(Payload{opcode: OBSOpcode::Reidentify, data})
+    }
+
+    pub fn opcode_reidentify_message(event_subscriptions: OBSEventSubscription) -> Value {
+        json!({
+            "op": 3,
+            "d": {
+                "eventSubscriptions": event_subscriptions.bits()
             }
+        })
+    }
+
+    fn opcode_event_from_data(data: Value) -> Result<Payload, Error> {
+        Payload::check_required_attributes(&data, 5, &["/eventType", "/eventIntent"])?;
+        Ok(Payload{opcode: OBSOpcode::Event, data})
+    }
 
-            atts = format!("{atts}. Provided values: ");
+    fn opcode_request_from_data(_data: Value) -> Result<Payload, Error> {
+        Err(Error::payload(PayloadError::ClientMessage, Some("Request".to_string())))
+    }
 
-            for (key, value) in attributes {
-                atts = format!("{atts} {key}, {value};");
+    ///Builds a request. All strings in the request data are escaped when the message is serialized.
+    pub fn opcode_request_message(request_type: &str, request_id: &str, request_data: Value) -> Value {
+        json!({
+            "op": 6,
+            "d": {
+                "requestType": request_type,
+                "requestId": request_id,
+                "requestData": request_data
             }
-            return Err(Payload::error_strings(PayloadError::MissingRequiredAttribute, Some(atts)));
-        }
+        })
     }
 
-    fn opcode_identify_from_string(message: String) -> Result<Payload, String> {
-        Err("".to_string())
+    fn opcode_request_response_from_data(data: Value) -> Result<Payload, Error> {
+        Payload::check_required_attributes(&data, 7, &["/requestType", "/requestId", "/requestStatus/result", "/requestStatus/code"])?;
+        Ok(Payload{opcode: OBSOpcode::RequestResponse, data})
     }
 
-    pub fn opcode_identify_message(rpcversion: usize, authentication: Option<String>, event_subscriptions: OBSEventSubscription) -> String{
-        let mut message = "{".to_string();
-        message = message + "\"op\":1,";
-        message = message + "\"d\":{";
-        message = format!("{message}\"rpcVersion\":{rpcversion},");
-        match authentication {
-            Some(auth) => message = format!("{}\"authentication\":\"{}\",", message, auth),
-            None => ()
-        }
-        message = format!("{message}\"eventSubscriptions\":{}", obs_event_subscription_match_value(event_subscriptions));
-        message = message + "}}";
+    fn opcode_request_batch_from_data(_data: Value) -> Result<Payload, Error> {
+        Err(Error::payload(PayloadError::ClientMessage, Some("RequestBatch".to_string())))
+    }
 
-        println!("{message}");//Debug
-        message.to_string()
+    ///Builds a request batch. The requests are numbered in the order they were added to the batch.
+    pub fn opcode_request_batch_message(request_id: &str, batch: &RequestBatch) -> Value {
+        let execution_type: i8 = obs_request_batch_execution_type_match_value(batch.execution_type()).parse().unwrap_or(-1);
+        let requests: Vec<Value> = batch.requests().iter().enumerate().map(|(index, (request_type, request_data))| json!({
+            "requestType": request_type,
+            "requestId": index.to_string(),
+            "requestData": request_data
+        })).collect();
+
+        json!({
+            "op": 8,
+            "d": {
+                "requestId": request_id,
+                "haltOnFailure": batch.halt_on_failure(),
+                "executionType": execution_type,
+                "requests": requests
+            }
+        })
     }
 
-    fn opcode_identified_from_string(mut message: String) -> Result<Payload, String> {
-        message = message.replace("\"op\":2", "");
-        println!("{message}");
+    fn opcode_request_batch_response_from_data(data: Value) -> Result<Payload, Error> {
+        Payload::check_required_attributes(&data, 9, &["/requestId", "/results"])?;
+        Ok(Payload{opcode: OBSOpcode::RequestBatchResponse, data})
+    }
 
-        let attributes = match Payload::parse_attributes(message) {
-            Ok(attributes) => attributes,
-            Err(error) => return Err(error)
+    ///Splits a RequestBatchResponse into the responses of the single requests, in the order the requests were added to the batch
+    pub fn batch_results(&self) -> Vec<Payload> {
+        let mut results: Vec<Payload> = match self.data["results"].as_array() {
+            Some(results) => results.iter().map(|result| Payload{opcode: OBSOpcode::RequestResponse, data: result.clone()}).collect(),
+            None => Vec::new()
         };
+        //the request ids within a batch are the positions of the requests (see opcode_request_batch_message)
+        results.sort_by_key(|result| result.data["requestId"].as_str().and_then(|id| id.parse::<usize>().ok()).unwrap_or(usize::MAX));
+        results
+    }
 
-        let opcode = OBSOpcode::Identifyed;
-        match Payload::check_opcode_identified_attributes(&attributes) {
-            Ok(()) => Ok(Payload {opcode, attributes}),
-            Err(error) => Err(error)
+    ///Sorts a message recieved from OBS into events and responses, the same way for every client
+    pub(crate) fn route(self) -> Result<Route, Error> {
+        match self.opcode {
+            OBSOpcode::Event => {
+                let event = Event::from_payload(&self)?;
+                debug!("Recieved event {}", event.event_type());
+                Ok(Route::Event(event))
+            },
+            OBSOpcode::RequestResponse | OBSOpcode::RequestBatchResponse => {
+                let request_id = self.data["requestId"].as_str().unwrap_or_default().to_string();
+                Ok(Route::Response(request_id, self))
+            },
+            _ => Ok(Route::Other(self))
         }
     }
 
-    fn check_opcode_identified_attributes(attributes: &HashMap<String, String>) -> Result<(), String> {
-        let mut negotiated_rpcversion = false;
-
-        for (key, value) in attributes {
-            match key.as_str() {
-                "negotiatedRpcVersion" => negotiated_rpcversion = !value.is_empty(),
-                _ => ()
-            }
-        }
+    ///check that every attribute (given as JSON pointer) exists and is not null
+    fn check_required_attributes(data: &Value, opcode: u8, required: &[&str]) -> Result<(), Error> {
+        let missing: Vec<&str> = required.iter()
+            .filter(|pointer| data.pointer(pointer).is_none_or(Value::is_null))
+            .copied()
+            .collect();
 
-        if negotiated_rpcversion {
+        if missing.is_empty() {
             Ok(())
         }
         else {
-            Err(Payload::error_strings(PayloadError::MissingRequiredAttribute, Some("Opcode 2 missing \"negotiatedRpcVersion\"".to_string())))
+            Err(Error::payload(PayloadError::MissingRequiredAttribute, Some(format!("Opcode {opcode} missing {}. Provided values: {data}", missing.join(", ")))))
         }
     }
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PayloadError::InvalidJson => "OBS message is not valid JSON",
+            PayloadError::InvalidMessagePack => "OBS message is not valid MessagePack",
+            PayloadError::OpCodeNotFound => "Opcode missing in OBS message",
+            PayloadError::DataNotFound => "Data object missing in OBS message",
+            PayloadError::MissingRequiredAttribute => "A required attribute is missing",
+            PayloadError::ClientMessage => "OBS sent a message that only clients send",
+            PayloadError::InvalidResponseData => "Response data does not match the request",
+            PayloadError::InvalidRequestData => "Request data could not be serialized"
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::batch::RequestBatch;
+    use crate::error::Error;
+    use crate::obs::{OBSEventSubscription, OBSRequestBatchExecutionType, OBSRequestStatus};
+    use crate::requests::GetVersionResponse;
+
+    use super::{Payload, PayloadError};
+
+    fn scene_name_round_trip(scene: &str) {
+        let message = Payload::opcode_request_message("SetCurrentProgramScene", "id", json!({"sceneName": scene})).to_string();
+        let parsed: Value = serde_json::from_str(&message).expect("request is not valid JSON");
+
+        assert_eq!(parsed["op"], 6);
+        assert_eq!(parsed["d"]["requestType"], "SetCurrentProgramScene");
+        assert_eq!(parsed["d"]["requestData"]["sceneName"], scene);
+    }
 
-    fn opcode_reidentify_from_string(message: String) -> Result<Payload, String> {
-        Err("".to_string())
+    #[test]
+    fn request_escapes_quotes() {
+        scene_name_round_trip("My \"Live\" Scene");
     }
 
-    fn opcode_event_from_string(message: String) -> Result<Payload, String> {
-        Err("".to_string())
+    #[test]
+    fn request_escapes_backslashes() {
+        scene_name_round_trip("C:\\Scenes\\Intro\\");
     }
 
-    fn opcode_request_from_string(message: String) -> Result<Payload, String> {
-        Err("".to_string())
+    #[test]
+    fn request_escapes_control_characters() {
+        scene_name_round_trip("Line\nBreak\tTab\r\u{0}\u{1f}");
+        let message = Payload::opcode_request_message("GetSceneItemId", "id", json!({"sceneName": "a\nb"})).to_string();
+        assert!(!message.contains('\n'));
     }
 
-    fn opcode_request_response_from_string(mut message: String) -> Result<Payload, String> {
-        message = message.replace("\"op\":7", "");
-        message = message.replace("\"requestStatus\":", "");
-        message = message.replace("\"responseData\":", "");
-        println!("{message}");
+    #[test]
+    fn request_keeps_non_ascii_names() {
+        scene_name_round_trip("Übergang");
+        scene_name_round_trip("Straße 🎬 配信");
+    }
 
-        let opcode = OBSOpcode::Request;
-        match Payload::parse_attributes(message) {
-            Ok(attributes) => Ok(Payload{attributes, opcode}),
-            Err(error) => Err(error)
-        }
+    #[test]
+    fn failed_request_keeps_status() {
+        let response = json!({"op": 7, "d": {
+            "requestType": "SetCurrentProgramScene",
+            "requestId": "id",
+            "requestStatus": {"result": false, "code": 600, "comment": "No source was found by the name of `missing`."}
+        }});
+        let error = Payload::from_string(response.to_string()).unwrap().check_request_status().err().unwrap();
+
+        assert!(matches!(&error, Error::RequestFailed{request_type, ..} if request_type == "SetCurrentProgramScene"));
+        assert_eq!(error.request_status_code(), Some(OBSRequestStatus::ResourceNotFound));
+        assert_eq!(error.request_status().unwrap().comment(), Some("No source was found by the name of `missing`."));
     }
 
-    fn opcode_request_batch_from_string(message: String) -> Result<Payload, String> {
-        Err("".to_string())
-    }
-
-    fn opcode_request_batch_response_from_string(message: String) -> Result<Payload, String> {
-        Err("".to_string())
-    }
-
-    fn parse_attributes(mut message: String) -> Result<HashMap<String, String>, String>{
-        message = message.replace("\"", "");
-        let mut attributes: HashMap<String, String> = HashMap::new();
-        let split: Vec<&str> = message.split(",").collect();
-
-        for attribute in split {
-            if !attribute.is_empty() {
-                let values: Vec<&str> = attribute.split(":").collect();
-                if values.len() == 2 {
-                    if !values[0].is_empty() && !values[1].is_empty() {
-                        attributes.insert(values[0].to_string(), values[1].to_string());
-                    }
-                    else {
-                        
-                        return Err(Payload::error_strings(PayloadError::ParseEmptyValue, Some(attribute.to_string())))
-                    }
-                }
-                else {
-                    let mut str = attribute.to_string();
-                    str = format!("{str}, {}", values.len());
-                        for val in values {
-                            str = format!("{str}, {}", val);
-                        }
-                    return Err(Payload::error_strings(PayloadError::ParseWrongNumberOfValues, Some(str)))
-                }
-            }   
-        }
+    #[test]
+    fn typed_response_without_response_data() {
+        let response = Payload::from_string(json!({"op": 7, "d": {
+            "requestType": "SetCurrentProgramScene",
+            "requestId": "id",
+            "requestStatus": {"result": true, "code": 100}
+        }}).to_string()).unwrap();
+        response.typed_response::<()>().unwrap();
+
+        let error = response.typed_response::<GetVersionResponse>().err().unwrap();
+        assert!(matches!(error, Error::Payload{kind: PayloadError::InvalidResponseData, ..}));
+    }
 
-        Ok(attributes)
+    #[test]
+    fn typed_response_keeps_deserialize_errors() {
+        let response = Payload::from_string(json!({"op": 7, "d": {
+            "requestType": "GetVersion",
+            "requestId": "id",
+            "requestStatus": {"result": true, "code": 100},
+            "responseData": {"obsVersion": 30}
+        }}).to_string()).unwrap();
+        let error = response.typed_response::<GetVersionResponse>().err().unwrap();
+
+        assert!(matches!(error, Error::Payload{kind: PayloadError::InvalidResponseData, information: Some(information)} if information.contains("invalid type")));
     }
 
-    pub fn error_strings(payload_error: PayloadError, information: Option<String>) -> String {
-        let message = match payload_error {
-            PayloadError::OpCodeNotFound => "Opcode missing in OBS message",
-            PayloadError::ParseWrongNumberOfValues => "Parsing a attribute resulted in more or less than 2 values",
-            PayloadError::ParseEmptyValue => "A parsed attribute is empty",
-            PayloadError::MissingRequiredAttribute => "A required attribute is missing",
-        }.to_string();
+    #[test]
+    fn request_batch_numbers_its_requests() {
+        let mut batch = RequestBatch::new(OBSRequestBatchExecutionType::SerialFrame, true);
+        batch.add("SetCurrentProgramScene", json!({"sceneName": "Live"}));
+        batch.add("Sleep", json!({"sleepFrames": 2}));
+        let message = Payload::opcode_request_batch_message("batch", &batch);
+
+        assert_eq!(message, json!({"op": 8, "d": {
+            "requestId": "batch",
+            "haltOnFailure": true,
+            "executionType": 1,
+            "requests": [
+                {"requestType": "SetCurrentProgramScene", "requestId": "0", "requestData": {"sceneName": "Live"}},
+                {"requestType": "Sleep", "requestId": "1", "requestData": {"sleepFrames": 2}}
+            ]
+        }}));
+    }
 
-        match information {
-            Some(info) => format!("{}: {}", message, info),
-            None => message
-        }
+    #[test]
+    fn batch_results_keep_the_order_of_the_batch() {
+        let result = |id: &str| json!({"requestType": "Sleep", "requestId": id, "requestStatus": {"result": true, "code": 100}});
+        let response = json!({"op": 9, "d": {"requestId": "batch", "results": [result("2"), result("0"), result("1")]}});
+        let results = Payload::from_string(response.to_string()).unwrap().batch_results();
+
+        let ids: Vec<&Value> = results.iter().map(|result| &result.data()["requestId"]).collect();
+        assert_eq!(ids, ["0", "1", "2"]);
+        assert!(results.iter().all(|result| result.request_status().unwrap().result()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn identify_authentication_is_redacted() {
+        let message = Payload::opcode_identify_message(1, Some("c2VjcmV0".to_string()), OBSEventSubscription::None).to_string();
+        let redacted = Payload::redact(&message);
+
+        assert!(!redacted.contains("c2VjcmV0"));
+        assert_eq!(serde_json::from_str::<Value>(&redacted).unwrap()["d"]["authentication"], "<redacted>");
+        assert_eq!(Payload::redact("not json"), "not json");
+    }
+}
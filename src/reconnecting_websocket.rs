@@ -0,0 +1,434 @@
+use std::{sync::{mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError}, Arc, Condvar, Mutex, PoisonError}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+
+use log::{debug, info, warn};
+use rand::Rng;
+use serde_json::Value;
+
+use crate::{lock, batch::RequestBatch, close_frame::NORMAL_CLOSURE, connect_options::ConnectOptions, error::Error, event::Event, obs::{OBSEventSubscription, OBSWebSocketCloseCode}, payload::Payload, requests::Request, shared_websocket::SharedWebsocket, url::WebsocketUrl, websocket::{State, WebsocketError}};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+///Events and connection events not taken from their receivers are dropped (newest first) when the buffer is full
+const CHANNEL_CAPACITY: usize = 1024;
+
+///Connection to OBS that connects again when the connection is lost, e.g. because OBS was restarted.
+///
+///A supervisor thread watches the connection: it ends when the server closes it, the stream ends or
+///the server does not answer a ping in time. The supervisor then reconnects with exponential backoff and
+///identifies again with the same password and event subscriptions. Requests in flight fail when the connection is lost,
+///requests made while reconnecting fail right away. Event receivers keep getting events from the new connection.
+///Clones share the connection, it is closed when the last clone is dropped.
+///```no_run
+///# use obs_remote_1lt::{connect_options::ConnectOptions, obs::OBSEventSubscription, reconnecting_websocket::{ConnectionEvent, ReconnectOptions, ReconnectingWebsocket}, url::WebsocketUrl};
+///# fn run() -> Result<(), obs_remote_1lt::error::Error> {
+///let url = WebsocketUrl::parse("ws://127.0.0.1:4455")?;
+///let obs = ReconnectingWebsocket::connect_with(url, &ConnectOptions::new(), ReconnectOptions::new(), None, 1, OBSEventSubscription::Scenes)?;
+///for connection_event in obs.connection_events() {
+///    if connection_event == ConnectionEvent::Reconnected {
+///        println!("Back on scene {}", obs.call(&obs_remote_1lt::requests::GetCurrentProgramScene{})?.scene_name);
+///    }
+///}
+///# Ok(()) }
+///```
+#[derive(Clone)]
+pub struct ReconnectingWebsocket {
+    client: Arc<Client>,
+    request_timeout: Duration
+}
+
+///Backoff and heartbeat settings of a ReconnectingWebsocket
+#[derive(Clone, Debug)]
+pub struct ReconnectOptions {
+    initial_delay: Duration,
+    max_delay: Duration,
+    max_attempts: Option<u32>,
+    ping_interval: Duration,
+    ping_timeout: Duration
+}
+
+///Changes of the connection of a ReconnectingWebsocket, see ReconnectingWebsocket::connection_events
+#[derive(Clone, PartialEq, Debug)]
+pub enum ConnectionEvent {
+    ///The connection was lost, requests fail until it is reconnected
+    Disconnected{reason: String},
+    ///The given reconnect attempt starts after delay
+    Reconnecting{attempt: u32, delay: Duration},
+    ReconnectFailed{attempt: u32, error: String},
+    ///Connected and identified again
+    Reconnected,
+    ///No further attempts are made, because the maximum number of attempts was reached or OBS refused the session
+    GaveUp{reason: String}
+}
+
+//stops the supervisor and closes the connection when the last ReconnectingWebsocket is dropped
+struct Client {
+    supervisor: Arc<Supervisor>,
+    thread: Mutex<Option<JoinHandle<()>>>
+}
+
+//state shared between the clients and the supervisor thread
+struct Supervisor {
+    url: WebsocketUrl,
+    options: ConnectOptions,
+    reconnect: ReconnectOptions,
+    password: Option<String>,
+    rpcversion: usize,
+    event_subscriptions: OBSEventSubscription,
+    //None while reconnecting
+    connection: Mutex<Option<SharedWebsocket>>,
+    event_senders: Mutex<Vec<SyncSender<Event>>>,
+    connection_event_senders: Mutex<Vec<SyncSender<ConnectionEvent>>>,
+    stopped: Mutex<bool>,
+    stop: Condvar
+}
+
+impl ReconnectingWebsocket {
+    ///Connects to OBS at a ws:// or wss:// url with the default ReconnectOptions, see Websocket::connect
+    pub fn connect(url: &str, password: Option<String>, rpcversion: usize, event_subscriptions: OBSEventSubscription) -> Result<ReconnectingWebsocket, Error> {
+        ReconnectingWebsocket::connect_with(WebsocketUrl::parse(url)?, &ConnectOptions::default(), ReconnectOptions::default(), password, rpcversion, event_subscriptions)
+    }
+
+    ///Connects with the TLS, compression and encoding settings of options, see Websocket::connect_with.
+    ///The first connection is not retried, its error is returned.
+    pub fn connect_with(url: WebsocketUrl, options: &ConnectOptions, reconnect: ReconnectOptions, password: Option<String>, rpcversion: usize, event_subscriptions: OBSEventSubscription) -> Result<ReconnectingWebsocket, Error> {
+        let connection = SharedWebsocket::connect_with(url.clone(), options, password.clone(), rpcversion, event_subscriptions)?;
+        let supervisor = Arc::new(Supervisor{
+            url, password, rpcversion, event_subscriptions, reconnect,
+            options: options.clone(),
+            connection: Mutex::new(Some(connection.clone())),
+            event_senders: Mutex::new(Vec::new()),
+            connection_event_senders: Mutex::new(Vec::new()),
+            stopped: Mutex::new(false),
+            stop: Condvar::new()
+        });
+
+        let events = connection.events();
+        let thread = {
+            let supervisor = supervisor.clone();
+            thread::Builder::new().name("obs-websocket-supervisor".to_string()).spawn(move || supervisor.run(connection, events))
+        };
+        let thread = match thread {
+            Ok(thread) => thread,
+            Err(error) => return Err(Error::io(WebsocketError::ConnectError, error))
+        };
+        Ok(ReconnectingWebsocket{client: Arc::new(Client{supervisor, thread: Mutex::new(Some(thread))}), request_timeout: TIMEOUT})
+    }
+
+    pub fn url(&self) -> &WebsocketUrl {
+        &self.client.supervisor.url
+    }
+
+    ///State of the current connection, Initialising while reconnecting
+    pub fn state(&self) -> State {
+        let supervisor = &self.client.supervisor;
+        if *lock(&supervisor.stopped) {
+            return State::Closed;
+        }
+        match lock(&supervisor.connection).as_ref() {
+            Some(connection) => connection.state(),
+            None => State::Initialising
+        }
+    }
+
+    ///The current connection, None while reconnecting
+    pub fn connection(&self) -> Option<SharedWebsocket> {
+        lock(&self.client.supervisor.connection).clone()
+    }
+
+    ///Time this handle waits for the response of a single request, clones made afterwards keep it
+    pub fn set_request_timeout(&mut self, timeout: Duration) {
+        self.request_timeout = timeout;
+    }
+
+    ///Sends a request on the current connection and waits for its response, see Websocket::request
+    pub fn request(&self, request_type: &str, request_data: Value) -> Result<Payload, Error> {
+        self.current()?.request(request_type, request_data)
+    }
+
+    ///Sends a typed request and returns its typed response, see Websocket::call
+    pub fn call<R: Request>(&self, request: &R) -> Result<R::Response, Error> {
+        self.current()?.call(request)
+    }
+
+    ///Sends a request batch and waits for its response, see Websocket::request_batch
+    pub fn request_batch(&self, batch: &RequestBatch) -> Result<Vec<Payload>, Error> {
+        self.current()?.request_batch(batch)
+    }
+
+    fn current(&self) -> Result<SharedWebsocket, Error> {
+        let supervisor = &self.client.supervisor;
+        match lock(&supervisor.connection).as_ref() {
+            Some(connection) => {
+                let mut connection = connection.clone();
+                connection.set_request_timeout(self.request_timeout);
+                Ok(connection)
+            },
+            None if supervisor.is_stopped() => Err(Error::websocket(WebsocketError::NotConnected, None)),
+            None => Err(Error::websocket(WebsocketError::NotConnected, Some("reconnecting".to_string())))
+        }
+    }
+
+    ///Receiver of the events recieved from now on, over every connection. See SharedWebsocket::events.
+    ///It disconnects when the client is closed or gave up.
+    pub fn events(&self) -> Receiver<Event> {
+        self.client.supervisor.subscribe(&self.client.supervisor.event_senders)
+    }
+
+    ///Receiver of the changes of the connection from now on.
+    ///It disconnects when the client is closed or gave up.
+    pub fn connection_events(&self) -> Receiver<ConnectionEvent> {
+        self.client.supervisor.subscribe(&self.client.supervisor.connection_event_senders)
+    }
+
+    ///Stops reconnecting and closes the connection with the given status code and reason for every clone, see Websocket::close
+    pub fn close(&self, code: u16, reason: &str) -> Result<(), Error> {
+        self.client.close(code, reason)
+    }
+}
+
+impl ReconnectOptions {
+    ///Reconnects after 0.5 s, doubling the delay up to 30 s, without limit on the attempts.
+    ///Pings the server every 5 s and reconnects if it does not answer within 10 s.
+    pub fn new() -> ReconnectOptions {
+        ReconnectOptions{
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_attempts: None,
+            ping_interval: Duration::from_secs(5),
+            ping_timeout: Duration::from_secs(10)
+        }
+    }
+
+    ///Delay before the first attempt, doubled with every further attempt up to max_delay
+    pub fn delay(mut self, initial_delay: Duration, max_delay: Duration) -> ReconnectOptions {
+        self.initial_delay = initial_delay;
+        self.max_delay = max_delay.max(initial_delay);
+        self
+    }
+
+    ///Gives up after this many failed attempts in a row
+    pub fn max_attempts(mut self, max_attempts: u32) -> ReconnectOptions {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    ///Sends a ping after interval without a ping, the connection is lost if the pong does not arrive within timeout
+    pub fn heartbeat(mut self, interval: Duration, timeout: Duration) -> ReconnectOptions {
+        self.ping_interval = interval;
+        self.ping_timeout = timeout;
+        self
+    }
+
+    //exponential backoff with equal jitter, so clients that lost the same server do not reconnect at the same time
+    fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self.initial_delay.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1))).min(self.max_delay);
+        rand::thread_rng().gen_range(delay / 2..=delay)
+    }
+}
+
+impl Default for ReconnectOptions {
+    fn default() -> Self {
+        ReconnectOptions::new()
+    }
+}
+
+impl Client {
+    fn close(&self, code: u16, reason: &str) -> Result<(), Error> {
+        let supervisor = &self.supervisor;
+        *lock(&supervisor.stopped) = true;
+        supervisor.stop.notify_all();
+        //the supervisor ends once the connection is closed
+        let connection = lock(&supervisor.connection).take();
+        let result = match connection {
+            Some(connection) => connection.close(code, reason),
+            None => Ok(())
+        };
+        let thread = lock(&self.thread).take();
+        if let Some(thread) = thread {
+            let _ = thread.join();
+        }
+        result
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.close(NORMAL_CLOSURE, "");
+    }
+}
+
+impl Supervisor {
+    fn run(&self, mut connection: SharedWebsocket, mut events: Receiver<Event>) {
+        loop {
+            let reason = self.watch(&connection, &events);
+            if self.is_stopped() {
+                break;
+            }
+            *lock(&self.connection) = None;
+            info!("Connection to {} lost: {reason}", self.url);
+            self.notify(ConnectionEvent::Disconnected{reason: reason.clone()});
+            //OBS refused the session, e.g. it was kicked
+            if !can_reconnect(connection.close_frame().and_then(|close_frame| close_frame.obs_close_code())) {
+                self.notify(ConnectionEvent::GaveUp{reason});
+                break;
+            }
+
+            match self.reconnect() {
+                Some(reconnected) => {
+                    connection = reconnected;
+                    events = connection.events();
+                    //checked under the lock Client::close takes the connection with, so it either closes this connection or stopped is already set
+                    let mut current = lock(&self.connection);
+                    if self.is_stopped() {
+                        drop(current);
+                        let _ = connection.close(NORMAL_CLOSURE, "");
+                        break;
+                    }
+                    *current = Some(connection.clone());
+                    drop(current);
+                    self.notify(ConnectionEvent::Reconnected);
+                },
+                None => break
+            }
+        }
+
+        //disconnects the receivers
+        *lock(&self.stopped) = true;
+        lock(&self.event_senders).clear();
+        lock(&self.connection_event_senders).clear();
+    }
+
+    //forwards the events and pings the server until the connection is lost, returns why
+    fn watch(&self, connection: &SharedWebsocket, events: &Receiver<Event>) -> String {
+        let interval = self.reconnect.ping_interval;
+        let connected = Instant::now();
+        loop {
+            if self.is_stopped() {
+                return "closed".to_string();
+            }
+            let heartbeat = connection.heartbeat();
+            if heartbeat.is_expired(self.reconnect.ping_timeout) {
+                connection.abort();
+                return format!("no pong within {:?}", self.reconnect.ping_timeout);
+            }
+            //a new ping is only sent once the last one was answered, otherwise it would never expire
+            let (wake_up, ping) = match (heartbeat.last_ping(), heartbeat.last_pong()) {
+                (Some(ping), Some(pong)) if pong >= ping => (ping + interval, true),
+                (Some(ping), _) => (ping + self.reconnect.ping_timeout + Duration::from_millis(1), false),
+                (None, _) => (connected + interval, true)
+            };
+            let now = Instant::now();
+            if ping && now >= wake_up {
+                if let Err(error) = connection.send_ping() {
+                    debug!("Could not send ping: {error}");
+                }
+                continue;
+            }
+
+            match events.recv_timeout(wake_up.saturating_duration_since(now)) {
+                Ok(event) => self.forward(event),
+                Err(RecvTimeoutError::Timeout) => (),
+                //the reader thread ended
+                Err(RecvTimeoutError::Disconnected) => return match connection.close_frame() {
+                    Some(close_frame) => close_frame.to_string(),
+                    None => "connection lost".to_string()
+                }
+            }
+        }
+    }
+
+    //None if stopped or gave up
+    fn reconnect(&self) -> Option<SharedWebsocket> {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            if self.reconnect.max_attempts.is_some_and(|max_attempts| attempt > max_attempts) {
+                self.notify(ConnectionEvent::GaveUp{reason: format!("{} attempts failed", attempt - 1)});
+                return None;
+            }
+
+            let delay = self.reconnect.backoff(attempt);
+            debug!("Reconnecting to {} in {delay:?} (attempt {attempt})", self.url);
+            self.notify(ConnectionEvent::Reconnecting{attempt, delay});
+            if self.wait(delay) {
+                return None;
+            }
+
+            match SharedWebsocket::connect_with(self.url.clone(), &self.options, self.password.clone(), self.rpcversion, self.event_subscriptions) {
+                Ok(connection) => {
+                    info!("Reconnected to {} after {attempt} attempts", self.url);
+                    return Some(connection);
+                },
+                Err(error) => {
+                    warn!("Reconnect attempt {attempt} failed: {error}");
+                    let refused = !can_reconnect(error.close_code());
+                    self.notify(ConnectionEvent::ReconnectFailed{attempt, error: error.to_string()});
+                    if refused {
+                        self.notify(ConnectionEvent::GaveUp{reason: error.to_string()});
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    //waits the delay, returns true if the client was stopped in the meantime
+    fn wait(&self, delay: Duration) -> bool {
+        let stopped = lock(&self.stopped);
+        let (stopped, _) = self.stop.wait_timeout_while(stopped, delay, |stopped| !*stopped).unwrap_or_else(PoisonError::into_inner);
+        *stopped
+    }
+
+    fn is_stopped(&self) -> bool {
+        *lock(&self.stopped)
+    }
+
+    fn subscribe<T>(&self, senders: &Mutex<Vec<SyncSender<T>>>) -> Receiver<T> {
+        let (sender, receiver) = mpsc::sync_channel(CHANNEL_CAPACITY);
+        let mut senders = lock(senders);
+        //the sender is dropped right away once the supervisor ended, which disconnects the receiver
+        if !self.is_stopped() {
+            senders.push(sender);
+        }
+        receiver
+    }
+
+    fn forward(&self, event: Event) {
+        send(&self.event_senders, event);
+    }
+
+    fn notify(&self, connection_event: ConnectionEvent) {
+        send(&self.connection_event_senders, connection_event);
+    }
+}
+
+fn send<T: Clone>(senders: &Mutex<Vec<SyncSender<T>>>, value: T) {
+    lock(senders).retain(|sender| match sender.try_send(value.clone()) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            warn!("Receiver full, dropping message");
+            true
+        },
+        Err(TrySendError::Disconnected(_)) => false
+    });
+}
+
+//a wrong password, an unsupported rpc version or a session invalidated in OBS will not change by reconnecting
+fn can_reconnect(close_code: Option<OBSWebSocketCloseCode>) -> bool {
+    !matches!(close_code, Some(OBSWebSocketCloseCode::AuthenticationFailed | OBSWebSocketCloseCode::UnsupportedRpcVersion | OBSWebSocketCloseCode::SessionInvalidated))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::ReconnectOptions;
+
+    #[test]
+    fn backoff_doubles_up_to_max_delay_with_jitter() {
+        let options = ReconnectOptions::new().delay(Duration::from_millis(100), Duration::from_secs(1));
+        for (attempt, delay) in [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1000), (40, 1000)] {
+            let backoff = options.backoff(attempt);
+            assert!(backoff >= Duration::from_millis(delay / 2) && backoff <= Duration::from_millis(delay), "attempt {attempt}: {backoff:?}");
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use std::fmt;
+
+use serde_json::Value;
+
+use crate::obs::{OBSRequestStatus, obs_request_status_match_enum, obs_request_status_match_value};
+
+///Result of a request as reported by OBS in the "requestStatus" field of a RequestResponse
+#[derive(Clone, PartialEq, Debug)]
+pub struct RequestStatus {
+    result: bool,
+    code: OBSRequestStatus,
+    comment: Option<String>
+}
+
+impl RequestStatus {
+    ///Parses the "requestStatus" object of a response. Unknown status codes are mapped to OBSRequestStatus::Unknown.
+    pub fn from_value(request_status: &Value) -> Option<RequestStatus> {
+        let result = request_status.get("result")?.as_bool()?;
+        let code = request_status.get("code")?.as_u64()?;
+        let code = obs_request_status_match_enum(code.to_string()).unwrap_or(OBSRequestStatus::Unknown);
+        let comment = request_status.get("comment").and_then(Value::as_str).map(str::to_string);
+
+        Some(RequestStatus{result, code, comment})
+    }
+
+    ///true if OBS executed the request
+    pub fn result(&self) -> bool {
+        self.result
+    }
+
+    pub fn code(&self) -> OBSRequestStatus {
+        self.code
+    }
+
+    ///Explanation sent by OBS if the request failed
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+}
+
+impl fmt::Display for RequestStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} ({})", self.code, obs_request_status_match_value(self.code))?;
+        if let Some(comment) = &self.comment {
+            write!(f, ": {comment}")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,82 @@
+//Typed requests, generated at build time from protocol/protocol.json (see build.rs).
+//Every request type has a struct of the same name with snake_case fields, optional fields are left out when None.
+//Requests with response data have a matching <RequestType>Response struct, the others respond with ().
+
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use crate::{error::Error, payload::PayloadError};
+
+///A request of the OBS websocket protocol with its typed response, see Websocket::call and RequestBatch::add_request
+pub trait Request: Serialize {
+    ///Value of the "requestType" field
+    const REQUEST_TYPE: &'static str;
+    ///Content of the "responseData" field
+    type Response: DeserializeOwned;
+
+    ///Content of the "requestData" field, fails if the Serialize implementation of a custom request fails
+    fn request_data(&self) -> Result<Value, Error> {
+        serde_json::to_value(self).map_err(|error| Error::payload(PayloadError::InvalidRequestData, Some(format!("{}: {error}", Self::REQUEST_TYPE))))
+    }
+}
+
+//OBS sends null for values that are not set (e.g. the preview scene outside of studio mode)
+pub(crate) fn null_as_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+include!(concat!(env!("OUT_DIR"), "/requests.rs"));
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn optional_fields_are_left_out() {
+        let request = GetSceneItemId {
+            scene_name: Some("Scene".to_string()),
+            source_name: "Camera".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(GetSceneItemId::REQUEST_TYPE, "GetSceneItemId");
+        assert_eq!(request.request_data().unwrap(), json!({"sceneName": "Scene", "sourceName": "Camera"}));
+    }
+
+    #[test]
+    fn request_data_that_cannot_be_serialized_fails() {
+        //json object keys have to be strings
+        #[derive(Serialize)]
+        struct SetPositions {
+            positions: std::collections::BTreeMap<(i32, i32), String>
+        }
+        impl Request for SetPositions {
+            const REQUEST_TYPE: &'static str = "SetPositions";
+            type Response = ();
+        }
+
+        let request = SetPositions{positions: [((0, 0), "Camera".to_string())].into()};
+        let error = request.request_data().err().unwrap();
+        assert!(matches!(error, Error::Payload{kind: PayloadError::InvalidRequestData, ..}), "{error}");
+    }
+
+    #[test]
+    fn null_and_missing_response_fields_become_default() {
+        let response: GetSceneListResponse = serde_json::from_value(json!({
+            "currentProgramSceneName": "Scene",
+            "currentPreviewSceneName": null,
+            "scenes": [{"sceneName": "Scene", "sceneIndex": 0}]
+        })).unwrap();
+
+        assert_eq!(response.current_program_scene_name, "Scene");
+        assert_eq!(response.current_preview_scene_name, "");
+        assert_eq!(response.current_preview_scene_uuid, "");
+        assert_eq!(response.scenes.len(), 1);
+    }
+}
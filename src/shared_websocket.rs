@@ -0,0 +1,442 @@
+use std::{collections::HashMap, io::Write, net::SocketAddr, sync::{mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError}, Arc, Condvar, Mutex, PoisonError}, thread::{self, JoinHandle}, time::Duration};
+
+use log::{debug, info, warn};
+use serde_json::Value;
+
+use crate::{lock, batch::RequestBatch, close_frame::{CloseFrame, NORMAL_CLOSURE}, codec::{self, Assembler, Control, Violation}, connect_options::ConnectOptions, deflate::{Deflate, DeflateParameters}, encoding::{self, Encoding}, error::Error, event::Event, handshake, header::Opcode, heartbeat::Heartbeat, message::Message, obs::{OBSEventSubscription, OBSOpcode}, payload::{Payload, Route}, requests::Request, stream::SharedStream, url::WebsocketUrl, websocket::{State, Websocket, WebsocketError}};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+///Events not taken from the receiver of events are dropped (newest first) when its buffer is full
+const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+///Connection to OBS that can be shared between threads, the blocking counterpart of AsyncWebsocket.
+///
+///A reader thread sorts the recieved messages into responses and events. Requests only need `&self`,
+///so several threads can have requests in flight at the same time, their responses are matched by request id.
+///Clones share the connection, it is closed when the last clone is dropped.
+///```no_run
+///# use obs_remote_1lt::{shared_websocket::SharedWebsocket, obs::OBSEventSubscription, requests::{CreateRecordChapter, GetVersion}};
+///# fn run() -> Result<(), obs_remote_1lt::error::Error> {
+///let obs = SharedWebsocket::connect("ws://127.0.0.1:4455", None, 1, OBSEventSubscription::Scenes)?;
+///let events = obs.events();
+///let chapters = obs.clone();
+///std::thread::spawn(move || chapters.call(&CreateRecordChapter{chapter_name: None}));
+///println!("OBS {}", obs.call(&GetVersion{})?.obs_version);
+///for event in events {
+///    println!("{}", event.event_type());
+///}
+///# Ok(()) }
+///```
+#[derive(Clone)]
+pub struct SharedWebsocket {
+    connection: Arc<Connection>,
+    request_timeout: Duration
+}
+
+//closes the connection when the last SharedWebsocket is dropped
+struct Connection {
+    url: WebsocketUrl,
+    ip: SocketAddr,
+    encoding: Encoding,
+    compression: Option<DeflateParameters>,
+    event_subscriptions: OBSEventSubscription,
+    shared: Arc<Shared>,
+    reader: Mutex<Option<JoinHandle<()>>>
+}
+
+//state shared between the clients and the reader thread
+struct Shared {
+    //frames are compressed and written under this lock, so they reach the server in the order of their compression
+    writer: Mutex<SharedStream>,
+    deflate: Mutex<Option<Deflate>>,
+    pending: Mutex<HashMap<String, SyncSender<Payload>>>,
+    event_senders: Mutex<Vec<SyncSender<Event>>>,
+    close_frame: Mutex<Option<CloseFrame>>,
+    heartbeat: Mutex<Heartbeat>,
+    state: Mutex<State>,
+    state_changed: Condvar
+}
+
+//reads and reassembles the frames of the connection, runs on the reader thread once the connection is identified
+struct FrameReader {
+    stream: SharedStream,
+    assembler: Assembler,
+    shared: Arc<Shared>
+}
+
+//removes the request from the pending requests when the request finished or timed out
+struct PendingRequest<'a> {
+    shared: &'a Shared,
+    request_id: String
+}
+
+impl SharedWebsocket {
+    ///Connects to OBS at a ws:// or wss:// url, see Websocket::connect
+    pub fn connect(url: &str, password: Option<String>, rpcversion: usize, event_subscriptions: OBSEventSubscription) -> Result<SharedWebsocket, Error> {
+        SharedWebsocket::connect_with(WebsocketUrl::parse(url)?, &ConnectOptions::default(), password, rpcversion, event_subscriptions)
+    }
+
+    ///Connects with the TLS, compression and encoding settings of options, see Websocket::connect_with
+    pub fn connect_with(url: WebsocketUrl, options: &ConnectOptions, password: Option<String>, rpcversion: usize, event_subscriptions: OBSEventSubscription) -> Result<SharedWebsocket, Error> {
+        let (url, ip, mut stream) = Websocket::open_stream(url, options)?;
+        let deflate = Websocket::upgrade(&url, &mut stream, options)?;
+        let compression = deflate.as_ref().map(Deflate::parameters);
+
+        let (read, write) = match stream.split() {
+            Ok(halves) => halves,
+            Err(error) => return Err(Error::io(WebsocketError::ConnectError, error))
+        };
+        let shared = Arc::new(Shared{
+            writer: Mutex::new(write),
+            deflate: Mutex::new(deflate),
+            pending: Mutex::new(HashMap::new()),
+            event_senders: Mutex::new(Vec::new()),
+            close_frame: Mutex::new(None),
+            heartbeat: Mutex::new(Heartbeat::new()),
+            state: Mutex::new(State::Initialising),
+            state_changed: Condvar::new()
+        });
+        let mut reader = FrameReader{stream: read, assembler: Assembler::new(), shared: shared.clone()};
+
+        //OBS Protocol initialisation
+        //read opcode 0 | Hello
+        let hello = encoding::decode(&reader.read_message()?)?;
+
+        //send opcode 1 | Identify
+        let identify = handshake::identify_message(hello, rpcversion, password, event_subscriptions)?;
+        shared.send(options.encoding.encode(&identify)?)?;
+
+        //read opcode 2 | Identified
+        let identified = encoding::decode(&reader.read_message()?)?;
+        if identified.opcode() != OBSOpcode::Identifyed {
+            return Err(Error::websocket(WebsocketError::NotIdentified, Some(identified.data().to_string())));
+        }
+        info!("Connected to {url} ({ip}, rpc version {}, {})", identified.data()["negotiatedRpcVersion"], options.encoding.subprotocol());
+
+        //the reader thread waits for messages until the connection ends
+        if let Err(error) = reader.stream.set_read_timeout(None) {
+            return Err(Error::io(WebsocketError::SetReadTimeoutError, error));
+        }
+        shared.set_state(State::Open);
+        let reader = thread::Builder::new().name("obs-websocket-reader".to_string()).spawn(move || reader.run());
+        let reader = match reader {
+            Ok(reader) => reader,
+            Err(error) => return Err(Error::io(WebsocketError::ConnectError, error))
+        };
+
+        let connection = Connection{
+            url, ip, compression, event_subscriptions,
+            encoding: options.encoding,
+            shared,
+            reader: Mutex::new(Some(reader))
+        };
+        Ok(SharedWebsocket{connection: Arc::new(connection), request_timeout: TIMEOUT})
+    }
+
+    ///permessage-deflate parameters if the server agreed to compression
+    pub fn compression(&self) -> Option<DeflateParameters> {
+        self.connection.compression
+    }
+
+    ///Encoding of the OBS messages, negotiated as subprotocol
+    pub fn encoding(&self) -> Encoding {
+        self.connection.encoding
+    }
+
+    ///Address the connection was made to
+    pub fn ip(&self) -> SocketAddr {
+        self.connection.ip
+    }
+
+    pub fn url(&self) -> &WebsocketUrl {
+        &self.connection.url
+    }
+
+    pub fn state(&self) -> State {
+        self.connection.shared.state()
+    }
+
+    ///Event categories this session is subscribed to
+    pub fn event_subscriptions(&self) -> OBSEventSubscription {
+        self.connection.event_subscriptions
+    }
+
+    ///The close frame recieved from the server, if the server closed the connection
+    pub fn close_frame(&self) -> Option<CloseFrame> {
+        lock(&self.connection.shared.close_frame).clone()
+    }
+
+    ///Time this handle waits for the response of a single request, clones made afterwards keep it
+    pub fn set_request_timeout(&mut self, timeout: Duration) {
+        self.request_timeout = timeout;
+    }
+
+    ///Sends a request and waits for its response, see Websocket::request
+    pub fn request(&self, request_type: &str, request_data: Value) -> Result<Payload, Error> {
+        let request_id = handshake::new_request_id();
+        let payload = Payload::opcode_request_message(request_type, &request_id, request_data);
+        debug!("Sending request {request_type} ({request_id})");
+
+        self.exchange(request_id, payload)?.check_request_status()
+    }
+
+    ///Sends a typed request and returns its typed response, see Websocket::call
+    pub fn call<R: Request>(&self, request: &R) -> Result<R::Response, Error> {
+        self.request(R::REQUEST_TYPE, request.request_data()?)?.typed_response()
+    }
+
+    ///Sends a request batch and waits for its response, see Websocket::request_batch
+    pub fn request_batch(&self, batch: &RequestBatch) -> Result<Vec<Payload>, Error> {
+        let request_id = handshake::new_request_id();
+        let payload = Payload::opcode_request_batch_message(&request_id, batch);
+        debug!("Sending request batch of {} requests ({request_id})", batch.len());
+
+        Ok(self.exchange(request_id, payload)?.batch_results())
+    }
+
+    //sends a request message and waits until the reader thread recieved the response with the same request id
+    fn exchange(&self, request_id: String, payload: Value) -> Result<Payload, Error> {
+        let shared = &self.connection.shared;
+        if shared.state() != State::Open {
+            return Err(shared.closed_error());
+        }
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let _pending = PendingRequest::new(shared, request_id.clone(), sender)?;
+
+        shared.send(self.connection.encoding.encode(&payload)?)?;
+        match receiver.recv_timeout(self.request_timeout) {
+            Ok(payload) => Ok(payload),
+            //the reader thread drops the pending requests when the connection ends
+            Err(RecvTimeoutError::Disconnected) => Err(shared.closed_error()),
+            Err(RecvTimeoutError::Timeout) => Err(Error::websocket(WebsocketError::RequestTimeout, Some(request_id)))
+        }
+    }
+
+    ///Receiver of the events recieved from now on. Every receiver gets every event, events recieved before the call are not included.
+    ///The receiver buffers up to 1024 events, further events are dropped for this receiver. It disconnects when the connection is closed.
+    pub fn events(&self) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::sync_channel(EVENT_CHANNEL_CAPACITY);
+        let shared = &self.connection.shared;
+        let mut event_senders = lock(&shared.event_senders);
+        //the sender is dropped right away on a closed connection, which disconnects the receiver
+        if shared.state() != State::Closed {
+            event_senders.push(sender);
+        }
+        receiver
+    }
+
+    ///Sends a ping to the server, the answering pong is recorded in the heartbeat
+    pub fn send_ping(&self) -> Result<(), Error> {
+        let shared = &self.connection.shared;
+        shared.send(Message::new(true, Opcode::Ping, true, String::new()))?;
+        lock(&shared.heartbeat).ping_sent();
+        Ok(())
+    }
+
+    ///Pings sent and pongs recieved on this connection
+    pub fn heartbeat(&self) -> Heartbeat {
+        lock(&self.connection.shared.heartbeat).clone()
+    }
+
+    //ends the connection without closing handshake, e.g. when the server stopped answering pings
+    pub(crate) fn abort(&self) {
+        self.connection.shared.shutdown();
+    }
+
+    ///Closes the connection with the given status code and reason for every clone, see Websocket::close.
+    ///Requests still in flight fail.
+    pub fn close(&self, code: u16, reason: &str) -> Result<(), Error> {
+        self.connection.close(code, reason)
+    }
+}
+
+impl Connection {
+    fn close(&self, code: u16, reason: &str) -> Result<(), Error> {
+        let shared = &self.shared;
+        {
+            let mut state = lock(&shared.state);
+            if matches!(*state, State::Closing | State::Closed) {
+                drop(state);
+                self.join_reader();
+                return Ok(());
+            }
+            *state = State::Closing;
+        }
+
+        debug!("Closing connection ({code} {reason})");
+        let result = shared.send(CloseFrame::new(code, reason).to_message());
+        //the reader thread ends when the close frame of the server arrives
+        let state = lock(&shared.state);
+        let (state, _) = shared.state_changed.wait_timeout_while(state, TIMEOUT, |state| *state != State::Closed).unwrap_or_else(PoisonError::into_inner);
+        let closed = *state == State::Closed;
+        drop(state);
+        if !closed {
+            //a blocking read returns the end of the stream
+            shared.shutdown();
+        }
+        self.join_reader();
+        result
+    }
+
+    fn join_reader(&self) {
+        let reader = lock(&self.reader).take();
+        if let Some(reader) = reader {
+            let _ = reader.join();
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let _ = self.close(NORMAL_CLOSURE, "");
+    }
+}
+
+impl Shared {
+    //frames are compressed and written under the writer lock, see codec::frame_bytes
+    fn send(&self, message: Message) -> Result<(), Error> {
+        let mut writer = lock(&self.writer);
+        let bytes = codec::frame_bytes(message, lock(&self.deflate).as_mut())?;
+        match writer.write_all(&bytes).and_then(|()| writer.flush()) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(Error::io(WebsocketError::WriteError, error))
+        }
+    }
+
+    //hands a recieved message to the event receivers or the request waiting for it
+    fn dispatch(&self, payload: Payload) {
+        match payload.route() {
+            Ok(Route::Event(event)) => lock(&self.event_senders).retain(|sender| match sender.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!("Event receiver full, dropping event");
+                    true
+                },
+                Err(TrySendError::Disconnected(_)) => false
+            }),
+            Ok(Route::Response(id, payload)) => match lock(&self.pending).remove(&id) {
+                Some(sender) => {
+                    debug!("Recieved response to {id}");
+                    let _ = sender.send(payload);
+                },
+                //response to an unknown or timed out request
+                None => warn!("Dropping response to unknown request {id}")
+            },
+            Ok(Route::Other(payload)) => debug!("Ignoring message with opcode {:?}", payload.opcode()),
+            Err(error) => warn!("Dropping invalid event: {error}")
+        }
+    }
+
+    fn state(&self) -> State {
+        *lock(&self.state)
+    }
+
+    fn set_state(&self, state: State) {
+        *lock(&self.state) = state;
+        self.state_changed.notify_all();
+    }
+
+    //fails the pending requests and disconnects the event receivers
+    fn closed(&self) {
+        self.set_state(State::Closed);
+        lock(&self.pending).clear();
+        lock(&self.event_senders).clear();
+    }
+
+    fn shutdown(&self) {
+        lock(&self.writer).shutdown();
+    }
+
+    fn closed_error(&self) -> Error {
+        Error::closed(lock(&self.close_frame).clone())
+    }
+}
+
+impl<'a> PendingRequest<'a> {
+    fn new(shared: &'a Shared, request_id: String, sender: SyncSender<Payload>) -> Result<PendingRequest<'a>, Error> {
+        let mut pending = lock(&shared.pending);
+        //checked under the lock, Shared::closed clears the pending requests after changing the state
+        if shared.state() == State::Closed {
+            return Err(shared.closed_error());
+        }
+        pending.insert(request_id.clone(), sender);
+        Ok(PendingRequest{shared, request_id})
+    }
+}
+
+impl Drop for PendingRequest<'_> {
+    fn drop(&mut self) {
+        lock(&self.shared.pending).remove(&self.request_id);
+    }
+}
+
+impl FrameReader {
+    fn run(mut self) {
+        loop {
+            let message = match self.read_message() {
+                Ok(message) => message,
+                Err(error) => {
+                    debug!("Connection ended: {error}");
+                    break;
+                }
+            };
+            match encoding::decode(&message) {
+                Ok(payload) => self.shared.dispatch(payload),
+                Err(error) => warn!("Dropping invalid message: {error}")
+            }
+        }
+        self.shared.shutdown();
+        self.shared.closed();
+    }
+
+    //same checks as Websocket::read_message
+    fn read_message(&mut self) -> Result<Message, Error> {
+        match self.next_message() {
+            Ok(message) => Ok(message),
+            Err(violation) => {
+                if let Some((code, reason)) = violation.close {
+                    self.fail(code, reason);
+                }
+                Err(violation.error)
+            }
+        }
+    }
+
+    fn next_message(&mut self) -> Result<Message, Violation> {
+        loop {
+            let frame = codec::read_frame(&mut self.stream, &self.assembler)?;
+            if frame.header().is_control_frame() {
+                self.handle_control_frame(frame)?;
+                continue;
+            }
+
+            let message = self.assembler.push(frame, lock(&self.shared.deflate).as_mut())?;
+            if let Some(message) = message {
+                return Ok(message);
+            }
+        }
+    }
+
+    fn handle_control_frame(&mut self, frame: Message) -> Result<(), Violation> {
+        match codec::control_frame(&frame, self.shared.state() == State::Closing)? {
+            Control::Ping(pong) => Ok(self.shared.send(pong)?),
+            Control::Pong => {
+                lock(&self.shared.heartbeat).pong_recieved();
+                Ok(())
+            },
+            Control::Close{close_frame, reply} => {
+                if let Some(reply) = reply {
+                    let _ = self.shared.send(reply);
+                }
+                *lock(&self.shared.close_frame) = Some(close_frame.clone());
+                Err(Error::ConnectionClosed(close_frame).into())
+            }
+        }
+    }
+
+    //sends a close frame after a protocol violation of the server, the reader thread ends with the returned error
+    fn fail(&self, code: u16, reason: &str) {
+        self.shared.set_state(State::Closing);
+        let _ = self.shared.send(CloseFrame::new(code, reason).to_message());
+    }
+}
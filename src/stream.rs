@@ -0,0 +1,201 @@
+use std::{io::{self, Read, Write}, net::{Shutdown, TcpStream}, time::Duration};
+#[cfg(feature = "tls")]
+use std::sync::{Arc, Mutex, PoisonError};
+
+#[cfg(feature = "tls")]
+use rustls::{ClientConnection, StreamOwned};
+
+//Connection under the WebSocket, a plain TCP stream (ws://) or TLS over TCP (wss://)
+pub(crate) enum Stream {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(Box<StreamOwned<ClientConnection, TcpStream>>)
+}
+
+//Half of a split Stream, one is read by the reader thread of a SharedWebsocket while the other is written by its requests.
+//A blocking read does not hold the TLS connection, it only waits on the TCP stream.
+pub(crate) struct SharedStream {
+    tcp: TcpStream,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<Mutex<ClientConnection>>>
+}
+
+impl Stream {
+    #[cfg(feature = "tls")]
+    pub(crate) fn tls(mut connection: ClientConnection, mut tcp: TcpStream) -> io::Result<Stream> {
+        while connection.is_handshaking() {
+            connection.complete_io(&mut tcp)?;
+        }
+        Ok(Stream::Tls(Box::new(StreamOwned::new(connection, tcp))))
+    }
+
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(stream) => stream,
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => &stream.sock
+        }
+    }
+
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp().set_read_timeout(timeout)
+    }
+
+    ///Splits the stream into a half for reading and a half for writing
+    pub(crate) fn split(self) -> io::Result<(SharedStream, SharedStream)> {
+        match self {
+            Stream::Plain(tcp) => Ok((SharedStream{tcp: tcp.try_clone()?, #[cfg(feature = "tls")] tls: None}, SharedStream{tcp, #[cfg(feature = "tls")] tls: None})),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => {
+                let (connection, tcp) = stream.into_parts();
+                let connection = Arc::new(Mutex::new(connection));
+                Ok((SharedStream{tcp: tcp.try_clone()?, tls: Some(connection.clone())}, SharedStream{tcp, tls: Some(connection)}))
+            }
+        }
+    }
+
+    pub(crate) fn shutdown(&mut self) {
+        #[cfg(feature = "tls")]
+        if let Stream::Tls(stream) = self {
+            stream.conn.send_close_notify();
+            let _ = stream.conn.complete_io(&mut stream.sock);
+        }
+        let _ = self.tcp().shutdown(Shutdown::Both);
+    }
+
+    ///Waits up to the read timeout for data without consuming it. Returns false at the end of the stream.
+    pub(crate) fn has_data(&mut self) -> io::Result<bool> {
+        match self {
+            Stream::Plain(stream) => {
+                let mut buffer = [0u8; 1];
+                Ok(stream.peek(&mut buffer)? > 0)
+            },
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => {
+                //records without application data (e.g. session tickets) are processed and waited past
+                loop {
+                    let state = stream.conn.process_new_packets().map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+                    if state.plaintext_bytes_to_read() > 0 {
+                        return Ok(true);
+                    }
+                    if state.peer_has_closed() {
+                        return Ok(false);
+                    }
+                    if stream.conn.read_tls(&mut stream.sock)? == 0 {
+                        return Ok(false);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buffer),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.read(buffer)
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buffer),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.write(buffer)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(stream) => stream.flush()
+        }
+    }
+}
+
+impl SharedStream {
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp.set_read_timeout(timeout)
+    }
+
+    ///Shuts down both halves, a blocking read returns the end of the stream
+    pub(crate) fn shutdown(&mut self) {
+        #[cfg(feature = "tls")]
+        if let Some(connection) = self.tls.as_ref() {
+            let mut connection = connection.lock().unwrap_or_else(PoisonError::into_inner);
+            connection.send_close_notify();
+            let _ = write_tls(&mut connection, &mut self.tcp);
+        }
+        let _ = self.tcp.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(feature = "tls")]
+fn write_tls(connection: &mut ClientConnection, tcp: &mut TcpStream) -> io::Result<()> {
+    while connection.wants_write() {
+        connection.write_tls(tcp)?;
+    }
+    Ok(())
+}
+
+impl Read for SharedStream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        #[cfg(feature = "tls")]
+        if let Some(connection) = self.tls.as_ref() {
+            let mut records = [0u8; 16 * 1024];
+            loop {
+                {
+                    let mut connection = connection.lock().unwrap_or_else(PoisonError::into_inner);
+                    match connection.reader().read(buffer) {
+                        Err(error) if error.kind() == io::ErrorKind::WouldBlock => (),
+                        result => return result
+                    }
+                }
+
+                //wait for the next records without holding the connection
+                let length = self.tcp.read(&mut records)?;
+                let mut connection = connection.lock().unwrap_or_else(PoisonError::into_inner);
+                if length == 0 {
+                    return match connection.reader().read(buffer) {
+                        Err(error) if error.kind() == io::ErrorKind::WouldBlock => Ok(0),
+                        result => result
+                    };
+                }
+                let mut records = &records[..length];
+                while !records.is_empty() {
+                    connection.read_tls(&mut records)?;
+                    if let Err(error) = connection.process_new_packets() {
+                        //sends the alert before failing
+                        let _ = write_tls(&mut connection, &mut self.tcp);
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+                    }
+                }
+                //e.g. answers to key updates
+                write_tls(&mut connection, &mut self.tcp)?;
+            }
+        }
+        self.tcp.read(buffer)
+    }
+}
+
+impl Write for SharedStream {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        #[cfg(feature = "tls")]
+        if let Some(connection) = self.tls.as_ref() {
+            let mut connection = connection.lock().unwrap_or_else(PoisonError::into_inner);
+            let length = connection.writer().write(buffer)?;
+            write_tls(&mut connection, &mut self.tcp)?;
+            return Ok(length);
+        }
+        self.tcp.write(buffer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.tcp.flush()
+    }
+}
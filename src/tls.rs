@@ -0,0 +1,200 @@
+use std::{fmt, path::Path, sync::Arc};
+
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{self, WebPkiSupportedAlgorithms};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime, pem::PemObject};
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+use crate::websocket::WebsocketError;
+
+///Settings of wss:// connections.
+///
+///By default the server certificate has to be signed by one of the Mozilla root certificates (webpki-roots)
+///and match the host name of the url, which is also sent as SNI.
+///```no_run
+///# use obs_remote_1lt::tls::TlsConfig;
+///# fn main() -> Result<(), obs_remote_1lt::error::Error> {
+/////self-signed certificate of the proxy, connected to by IP address
+///let tls = TlsConfig::new()
+///    .without_webpki_roots()
+///    .add_root_certificates_pem("proxy.pem")?
+///    .server_name("obs.example.org");
+///# Ok(()) }
+///```
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    webpki_roots: bool,
+    root_certificates: Vec<CertificateDer<'static>>,
+    pinned_certificates: Vec<[u8; 32]>,
+    server_name: Option<String>,
+    sni: bool
+}
+
+impl Default for TlsConfig {
+    fn default() -> TlsConfig {
+        TlsConfig::new()
+    }
+}
+
+impl TlsConfig {
+    pub fn new() -> TlsConfig {
+        TlsConfig{webpki_roots: true, root_certificates: Vec::new(), pinned_certificates: Vec::new(), server_name: None, sni: true}
+    }
+
+    ///Only trusts the root certificates added to this config
+    pub fn without_webpki_roots(mut self) -> TlsConfig {
+        self.webpki_roots = false;
+        self
+    }
+
+    ///Trusts every certificate of a PEM file (e.g. a self-signed certificate or a private CA)
+    pub fn add_root_certificates_pem(mut self, path: impl AsRef<Path>) -> Result<TlsConfig, Error> {
+        let path = path.as_ref();
+        let certificate_error = |error: &dyn fmt::Display| Error::websocket(WebsocketError::TlsCertificateError, Some(format!("{}: {error}", path.display())));
+
+        let certificates = CertificateDer::pem_file_iter(path)
+            .map_err(|error| certificate_error(&error))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|error| certificate_error(&error))?;
+        if certificates.is_empty() {
+            return Err(certificate_error(&"no certificate found"));
+        }
+        self.root_certificates.extend(certificates);
+        Ok(self)
+    }
+
+    ///Trusts a DER encoded certificate
+    pub fn add_root_certificate_der(mut self, certificate: Vec<u8>) -> TlsConfig {
+        self.root_certificates.push(CertificateDer::from(certificate));
+        self
+    }
+
+    ///Pins the server certificate by the SHA-256 fingerprint of its DER encoding.
+    ///With pins the server certificate is accepted if it matches one of them, root certificates, expiry
+    ///and host name are not checked then. Pinning a self-signed certificate needs no root certificate.
+    pub fn pin_sha256(mut self, fingerprint: [u8; 32]) -> TlsConfig {
+        self.pinned_certificates.push(fingerprint);
+        self
+    }
+
+    ///Pins a fingerprint written in hex, with or without colons ("AB:CD:..." as shown by openssl x509 -fingerprint -sha256)
+    pub fn pin_sha256_hex(self, fingerprint: &str) -> Result<TlsConfig, Error> {
+        match parse_fingerprint(fingerprint) {
+            Some(fingerprint) => Ok(self.pin_sha256(fingerprint)),
+            None => Err(Error::websocket(WebsocketError::TlsCertificateError, Some(format!("invalid SHA-256 fingerprint: {fingerprint}"))))
+        }
+    }
+
+    ///Name the certificate is checked against and sent as SNI instead of the host of the url,
+    ///e.g. when connecting to a proxy by IP address
+    pub fn server_name(mut self, name: &str) -> TlsConfig {
+        self.server_name = Some(name.to_string());
+        self
+    }
+
+    ///Sends no SNI (the certificate is still checked against the server name)
+    pub fn without_sni(mut self) -> TlsConfig {
+        self.sni = false;
+        self
+    }
+
+    pub(crate) fn server_name_for(&self, host: &str) -> Result<ServerName<'static>, Error> {
+        let name = self.server_name.as_deref().unwrap_or(host);
+        match ServerName::try_from(name.to_string()) {
+            Ok(name) => Ok(name),
+            Err(error) => Err(Error::websocket(WebsocketError::TlsCertificateError, Some(format!("invalid server name {name}: {error}"))))
+        }
+    }
+
+    pub(crate) fn client_config(&self) -> Result<Arc<ClientConfig>, Error> {
+        let provider = Arc::new(crypto::ring::default_provider());
+        let builder = match ClientConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions() {
+            Ok(builder) => builder,
+            Err(error) => return Err(Error::websocket(WebsocketError::TlsHandshakeError, Some(error.to_string())))
+        };
+
+        let mut config = if self.pinned_certificates.is_empty() {
+            let mut roots = RootCertStore::empty();
+            if self.webpki_roots {
+                roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+            }
+            for certificate in &self.root_certificates {
+                if let Err(error) = roots.add(certificate.clone()) {
+                    return Err(Error::websocket(WebsocketError::TlsCertificateError, Some(error.to_string())));
+                }
+            }
+            builder.with_root_certificates(roots).with_no_client_auth()
+        }
+        else {
+            let verifier = PinnedCertificates{fingerprints: self.pinned_certificates.clone(), algorithms: provider.signature_verification_algorithms};
+            builder.dangerous().with_custom_certificate_verifier(Arc::new(verifier)).with_no_client_auth()
+        };
+        config.enable_sni = self.sni;
+        Ok(Arc::new(config))
+    }
+}
+
+///SHA-256 fingerprint of a DER encoded certificate, as used by TlsConfig::pin_sha256
+pub fn certificate_sha256(certificate: &[u8]) -> [u8; 32] {
+    Sha256::digest(certificate).into()
+}
+
+fn parse_fingerprint(fingerprint: &str) -> Option<[u8; 32]> {
+    let hex: String = fingerprint.chars().filter(|character| *character != ':').collect();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0u8; 32];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+//accepts the certificates whose fingerprint is pinned, the handshake signatures are still verified
+#[derive(Debug)]
+struct PinnedCertificates {
+    fingerprints: Vec<[u8; 32]>,
+    algorithms: WebPkiSupportedAlgorithms
+}
+
+impl ServerCertVerifier for PinnedCertificates {
+    fn verify_server_cert(&self, end_entity: &CertificateDer<'_>, _intermediates: &[CertificateDer<'_>], _server_name: &ServerName<'_>, _ocsp_response: &[u8], _now: UnixTime) -> Result<ServerCertVerified, rustls::Error> {
+        if self.fingerprints.contains(&certificate_sha256(end_entity)) {
+            Ok(ServerCertVerified::assertion())
+        }
+        else {
+            Err(rustls::Error::General("server certificate does not match a pinned fingerprint".to_string()))
+        }
+    }
+
+    fn verify_tls12_signature(&self, message: &[u8], certificate: &CertificateDer<'_>, signature: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, certificate, signature, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(&self, message: &[u8], certificate: &CertificateDer<'_>, signature: &DigitallySignedStruct) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, certificate, signature, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_fingerprint;
+
+    #[test]
+    fn parse_fingerprint_with_and_without_colons() {
+        let hex = "00112233445566778899aabbccddeeff00112233445566778899AABBCCDDEEFF";
+        let with_colons = hex.as_bytes().chunks(2).map(|pair| std::str::from_utf8(pair).unwrap()).collect::<Vec<_>>().join(":");
+
+        assert_eq!(parse_fingerprint(hex).unwrap()[..4], [0x00, 0x11, 0x22, 0x33]);
+        assert_eq!(parse_fingerprint(hex), parse_fingerprint(&with_colons));
+        assert!(parse_fingerprint("0011").is_none());
+        assert!(parse_fingerprint(&hex.replace('0', "g")).is_none());
+    }
+}
@@ -0,0 +1,193 @@
+use std::{fmt, net::{IpAddr, SocketAddr, ToSocketAddrs}, str::FromStr};
+
+use crate::error::Error;
+use crate::websocket::WebsocketError;
+
+///Port of ws:// urls without port (RFC 6455 section 3)
+pub const DEFAULT_PORT: u16 = 80;
+///Port of wss:// urls without port
+pub const DEFAULT_SECURE_PORT: u16 = 443;
+
+///Address of a WebSocket server: ws://host:port/path, or wss://host:port/path for connections over TLS
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct WebsocketUrl {
+    secure: bool,
+    host: String,
+    port: u16,
+    path: String
+}
+
+impl WebsocketUrl {
+    ///Parses ws://host[:port][/path][?query] or wss://... IPv6 addresses are written in brackets (ws://[::1]:4455).
+    pub fn parse(url: &str) -> Result<WebsocketUrl, Error> {
+        WebsocketUrl::parse_with_default_port(url, DEFAULT_PORT)
+    }
+
+    ///Like parse, but ws:// urls without port get default_port (e.g. 4455, the port of obs-websocket)
+    pub fn parse_with_default_port(url: &str, default_port: u16) -> Result<WebsocketUrl, Error> {
+        let invalid = |reason: &str| Error::websocket(WebsocketError::InvalidUrl, Some(format!("{reason}: {url}")));
+
+        let (secure, rest) = match url.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("ws") => (false, rest),
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("wss") => (true, rest),
+            Some(_) => return Err(invalid("unsupported scheme, expected ws:// or wss://")),
+            None => return Err(invalid("missing scheme ws:// or wss://"))
+        };
+        if rest.contains('#') {
+            return Err(invalid("fragments are not allowed"));
+        }
+
+        let path_start = rest.find(['/', '?']).unwrap_or(rest.len());
+        let (authority, path) = rest.split_at(path_start);
+        if authority.contains('@') {
+            return Err(invalid("user information is not supported"));
+        }
+
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(ipv6) => match ipv6.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, port)) => match port.strip_prefix(':') {
+                    Some(port) => (host, Some(port)),
+                    None => return Err(invalid("invalid IPv6 address"))
+                },
+                None => return Err(invalid("invalid IPv6 address"))
+            },
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None)
+            }
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid("invalid port"))?,
+            None if secure => DEFAULT_SECURE_PORT,
+            None => default_port
+        };
+        let path = match path {
+            "" => "/".to_string(),
+            path if path.starts_with('?') => format!("/{path}"),
+            path => path.to_string()
+        };
+
+        Ok(WebsocketUrl{secure, host: host.to_string(), port, path})
+    }
+
+    ///ws:// url from parts, host is a host name or an IP address (IPv6 without brackets)
+    pub fn new(host: &str, port: u16, path: &str) -> WebsocketUrl {
+        let path = if path.starts_with('/') {
+            path.to_string()
+        }
+        else {
+            format!("/{path}")
+        };
+        WebsocketUrl{secure: false, host: host.to_string(), port, path}
+    }
+
+    pub fn from_socket_addr(address: SocketAddr) -> WebsocketUrl {
+        WebsocketUrl{secure: false, host: address.ip().to_string(), port: address.port(), path: "/".to_string()}
+    }
+
+    ///The same address with wss://
+    pub fn with_tls(mut self) -> WebsocketUrl {
+        self.secure = true;
+        self
+    }
+
+    ///true for wss://
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    pub fn scheme(&self) -> &str {
+        if self.secure { "wss" } else { "ws" }
+    }
+
+    ///Host name or IP address (IPv6 without brackets)
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    ///Path and query sent in the upgrade request
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    ///Value of the Host header, the port is left out if it is the default port
+    pub fn host_header(&self) -> String {
+        let host = match self.host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(_)) => format!("[{}]", self.host),
+            _ => self.host.clone()
+        };
+        let default_port = if self.secure { DEFAULT_SECURE_PORT } else { DEFAULT_PORT };
+        if self.port == default_port {
+            host
+        }
+        else {
+            format!("{host}:{}", self.port)
+        }
+    }
+
+    ///All addresses of the host (IPv4 and IPv6) in the order the resolver returned them
+    pub fn resolve(&self) -> Result<Vec<SocketAddr>, Error> {
+        match (self.host.as_str(), self.port).to_socket_addrs() {
+            Ok(addresses) => Ok(addresses.collect()),
+            Err(error) => Err(Error::io(WebsocketError::ResolveError, error))
+        }
+    }
+}
+
+impl FromStr for WebsocketUrl {
+    type Err = Error;
+
+    fn from_str(url: &str) -> Result<WebsocketUrl, Error> {
+        WebsocketUrl::parse(url)
+    }
+}
+
+impl fmt::Display for WebsocketUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}://{}{}", self.scheme(), self.host_header(), self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WebsocketUrl;
+
+    #[test]
+    fn parse_host_port_and_path() {
+        let url = WebsocketUrl::parse("ws://studio-pc:4455/obs?x=1").unwrap();
+        assert_eq!((url.host(), url.port(), url.path()), ("studio-pc", 4455, "/obs?x=1"));
+        assert_eq!(url.host_header(), "studio-pc:4455");
+
+        let url = WebsocketUrl::parse("WS://obs.local").unwrap();
+        assert_eq!((url.host(), url.port(), url.path()), ("obs.local", 80, "/"));
+        assert_eq!(url.host_header(), "obs.local");
+
+        let url = WebsocketUrl::parse("wss://obs.example.org/obs").unwrap();
+        assert!(url.is_secure());
+        assert_eq!((url.port(), url.host_header()), (443, "obs.example.org".to_string()));
+        assert_eq!(url.to_string(), "wss://obs.example.org/obs");
+    }
+
+    #[test]
+    fn parse_ipv6() {
+        let url = WebsocketUrl::parse("ws://[::1]:4455").unwrap();
+        assert_eq!((url.host(), url.port()), ("::1", 4455));
+        assert_eq!(url.to_string(), "ws://[::1]:4455/");
+    }
+
+    #[test]
+    fn reject_invalid_urls() {
+        for url in ["obs.local:4455", "http://obs.local", "ws://:4455", "ws://obs.local:port", "ws://user@obs.local", "ws://obs.local/#top", "ws://[::1"] {
+            assert!(WebsocketUrl::parse(url).is_err(), "{url}");
+        }
+    }
+}
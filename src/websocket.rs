@@ -1,25 +1,52 @@
-use std::{net::{SocketAddr, TcpStream}, io::{Write, Read, BufReader, BufRead}, time::Duration};
+use std::{fmt, collections::{HashSet, VecDeque}, net::{SocketAddr, TcpStream}, io::{self, ErrorKind, Write, Read}, time::{Duration, Instant}};
 
-use rand::{RngCore, Rng};
-use base64::{Engine as _, engine::general_purpose};
-use sha1::{Sha1, Digest};
-use sha2::Sha256;
+use log::{debug, info, warn};
+use serde_json::Value;
 
-use crate::{message::Message, header::Header, payload::Payload, obs::{OBSOpcode, OBSEventSubscription}};
+use crate::{batch::RequestBatch, error::Error, close_frame::CloseFrame, codec::{self, Assembler, Control, Violation}, connect_options::ConnectOptions, deflate::{Deflate, DeflateParameters}, encoding::{self, Encoding}, event::{Event, EventCallback, Events}, handshake, heartbeat::Heartbeat, message::Message, header::Opcode, payload::{Payload, Route}, obs::{OBSOpcode, OBSEventSubscription}, requests::Request, stream::Stream, url::WebsocketUrl};
+#[cfg(feature = "tls")]
+use crate::tls::TlsConfig;
 
 pub struct Websocket {
+    url: WebsocketUrl,
     ip: SocketAddr,
-    stream: TcpStream,
+    stream: Stream,
+    deflate: Option<Deflate>,
+    encoding: Encoding,
     state: State,
+    assembler: Assembler,
+    heartbeat: Option<Heartbeat>,
+    close_frame: Option<CloseFrame>,
+    request_timeout: Duration,
+    requests_in_flight: HashSet<String>,
+    buffered_responses: VecDeque<Payload>,
+    buffered_events: VecDeque<Event>,
+    event_callbacks: Vec<EventCallback>,
+    event_subscriptions: OBSEventSubscription
 }
 
+///Log target of the frame trace. Every sent and recieved frame is logged at trace level,
+///the authentication string of Identify is redacted.
+pub const FRAME_LOG_TARGET: &str = "obs_remote_1lt::frames";
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+///Events not collected with next_event are dropped (oldest first) when the buffer is full
+const MAX_BUFFERED_EVENTS: usize = 1024;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum State {
-    initialising,
-    open,
-    closed
+    Initialising,
+    Open,
+    Closing,
+    Closed
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum WebsocketError {
+    InvalidUrl,
+    ResolveError,
+    TlsHandshakeError,
+    TlsCertificateError,
     ConnectError,
     SetReadTimeoutError,
     SetWriteTimeoutError,
@@ -30,387 +57,580 @@ pub enum WebsocketError {
     UpgradeConncetionError,
     UpgradeAcceptError,
     UpgradeProtocolError,
+    UpgradeExtensionError,
+    DeflateError,
     MissingOpCode0,
     RpcVersionNotSupported,
     AuthenticationRequired,
-    NotIdentified
+    NotIdentified,
+    FragmentationError,
+    ControlFrameError,
+    MessageTooBig,
+    NotConnected,
+    RequestTimeout
 }
 
 impl Websocket {
-    pub fn new(ip: SocketAddr, password: Option<String>, rpcversion: usize) -> Result<Websocket, String> {
-        //initialise Websocket
-        let mut websocket = match Websocket::initialise_websocket(ip) {
-            Ok(websocket) => websocket,
-            Err(error) => return Err(error)
+    ///Connects to OBS at an IP address and identifies with the given event subscriptions (OBSEventSubscription::None to recieve no events)
+    pub fn new(ip: SocketAddr, password: Option<String>, rpcversion: usize, event_subscriptions: OBSEventSubscription) -> Result<Websocket, Error> {
+        Websocket::connect_url(WebsocketUrl::from_socket_addr(ip), password, rpcversion, event_subscriptions)
+    }
+
+    ///Connects to OBS at a ws:// or wss:// url (e.g. "ws://obs.local:4455"), see new.
+    ///Host names are resolved and every address (IPv4 and IPv6) is tried until one accepts the connection.
+    ///wss:// urls use the default TlsConfig, see connect_tls for other certificates.
+    pub fn connect(url: &str, password: Option<String>, rpcversion: usize, event_subscriptions: OBSEventSubscription) -> Result<Websocket, Error> {
+        Websocket::connect_url(WebsocketUrl::parse(url)?, password, rpcversion, event_subscriptions)
+    }
+
+    pub fn connect_url(url: WebsocketUrl, password: Option<String>, rpcversion: usize, event_subscriptions: OBSEventSubscription) -> Result<Websocket, Error> {
+        Websocket::connect_with(url, &ConnectOptions::default(), password, rpcversion, event_subscriptions)
+    }
+
+    ///Connects to OBS over TLS (wss://, also for ws:// urls), e.g. through a TLS terminating reverse proxy
+    #[cfg(feature = "tls")]
+    pub fn connect_tls(url: WebsocketUrl, tls: &TlsConfig, password: Option<String>, rpcversion: usize, event_subscriptions: OBSEventSubscription) -> Result<Websocket, Error> {
+        Websocket::connect_with(url, &ConnectOptions::new().tls(tls.clone()), password, rpcversion, event_subscriptions)
+    }
+
+    ///Connects with the TLS and compression settings of options, see connect
+    pub fn connect_with(url: WebsocketUrl, options: &ConnectOptions, password: Option<String>, rpcversion: usize, event_subscriptions: OBSEventSubscription) -> Result<Websocket, Error> {
+        let (url, ip, stream) = Websocket::open_stream(url, options)?;
+        Websocket::identify(url, ip, stream, options, password, rpcversion, event_subscriptions)
+    }
+
+    //TCP connection, with TLS for wss:// urls or if options has a TlsConfig. Returns the url with the wss:// scheme in that case.
+    #[cfg_attr(not(feature = "tls"), allow(unused_variables))]
+    pub(crate) fn open_stream(url: WebsocketUrl, options: &ConnectOptions) -> Result<(WebsocketUrl, SocketAddr, Stream), Error> {
+        #[cfg(feature = "tls")]
+        let (url, tls) = match (&options.tls, url.is_secure()) {
+            (Some(tls), _) => (url.with_tls(), Some(tls.clone())),
+            (None, true) => (url, Some(TlsConfig::default())),
+            (None, false) => (url, None)
         };
+        #[cfg(not(feature = "tls"))]
+        if url.is_secure() {
+            return Err(Error::websocket(WebsocketError::InvalidUrl, Some(format!("{url} needs the tls feature"))));
+        }
 
-        //OBS Protocol initialisation
-        //read opcode 0 | Hello
-        let hello_message = match websocket.read_message() {
-            Ok(message) => message,
-            Err(error) => return Err(error)
+        let (ip, stream) = Websocket::connect_tcp(&url)?;
+        #[cfg(feature = "tls")]
+        let stream = match tls {
+            Some(tls) => Websocket::start_tls(&url, ip, &tls, stream)?,
+            None => Stream::Plain(stream)
         };
-        println!("{}", hello_message.payload_value()); //debug
-        let payload = match Payload::from_string(hello_message.payload_value()) {
-            Ok(payload) => payload,
-            Err(error) => return Err(error)
+        #[cfg(not(feature = "tls"))]
+        let stream = Stream::Plain(stream);
+
+        Ok((url, ip, stream))
+    }
+
+    #[cfg(feature = "tls")]
+    fn start_tls(url: &WebsocketUrl, ip: SocketAddr, tls: &TlsConfig, stream: TcpStream) -> Result<Stream, Error> {
+        let connection = match rustls::ClientConnection::new(tls.client_config()?, tls.server_name_for(url.host())?) {
+            Ok(connection) => connection,
+            Err(error) => return Err(Error::websocket(WebsocketError::TlsHandshakeError, Some(error.to_string())))
         };
-        if payload.opcode() != OBSOpcode::Hello {
-            return Err(Websocket::error_strings(WebsocketError::MissingOpCode0, None));
+        match Stream::tls(connection, stream) {
+            Ok(stream) => {
+                debug!("TLS connection to {ip} established");
+                Ok(stream)
+            },
+            Err(error) => Err(Error::io(WebsocketError::TlsHandshakeError, error))
         }
+    }
+
+    fn identify(url: WebsocketUrl, ip: SocketAddr, stream: Stream, options: &ConnectOptions, password: Option<String>, rpcversion: usize, event_subscriptions: OBSEventSubscription) -> Result<Websocket, Error> {
+        //initialise Websocket
+        let mut websocket = Websocket::initialise_websocket(url, ip, stream, options)?;
+
+        //OBS Protocol initialisation
+        //read opcode 0 | Hello
+        let hello = websocket.read_payload_message()?;
 
         //send opcode 1 | Identify
-        let opcode1_payload = match Websocket::opcode1_payload(rpcversion, password, OBSEventSubscription::None, payload) {
-            Ok(message) => message,
-            Err(error) => return Err(error)
-        };
-        match websocket.send_message(Message::new(true, crate::header::Opcode::TextFrame, true, opcode1_payload)) {
-            Ok(()) => (),
-            Err(error) => return Err(error)
-        }
+        let opcode1_payload = handshake::identify_message(hello, rpcversion, password, event_subscriptions)?;
+        websocket.send_payload_message(opcode1_payload)?;
 
         //read opcode 2| Identified
-        let identified_message = match websocket.read_message() {
-            Ok(message) => message,
-            Err(error) => return Err(error)
-        };
-        println!("{}", identified_message.payload_value());
-
-        let identified_payload = match Payload::from_string(identified_message.payload_value()) {
-            Ok(payload) => payload,
-            Err(error) => return Err(error)
-        };
+        let identified_payload = websocket.read_payload_message()?;
 
         if identified_payload.opcode() == OBSOpcode::Identifyed {
+            info!("Connected to {} ({}, rpc version {}, {})", websocket.url, websocket.ip, identified_payload.data()["negotiatedRpcVersion"], websocket.encoding.subprotocol());
+            websocket.state = State::Open;
+            websocket.event_subscriptions = event_subscriptions;
             Ok(websocket)
         }
         else {
-            Err(Websocket::error_strings(WebsocketError::NotIdentified, Some(identified_message.payload_value())))
+            Err(Error::websocket(WebsocketError::NotIdentified, Some(identified_payload.data().to_string())))
         }
     }
 
-    //initialise
-    fn initialise_websocket(ip: SocketAddr) -> Result<Websocket, String> {
-        let sec_websocket_key = Websocket::new_sec_websocket_key();
-        let mut stream = match TcpStream::connect_timeout(&ip, Duration::from_secs(5)) {
-            Ok(stream) => stream,
-            Err(error) => return Err(Websocket::error_strings(WebsocketError::ConnectError, Some(format!("Could not connect to {}: {}", ip.to_string(), error.to_string()))))
-        };
+    ///permessage-deflate parameters if the server agreed to compression
+    pub fn compression(&self) -> Option<DeflateParameters> {
+        self.deflate.as_ref().map(Deflate::parameters)
+    }
 
-        //Set timeouts
-        match stream.set_read_timeout(Some(Duration::from_secs(5))) {
-            Ok(()) => (),
-            Err(error) => return Err(Websocket::error_strings(WebsocketError::SetReadTimeoutError, Some(error.to_string())))
+    ///Encoding of the OBS messages, negotiated as subprotocol
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    ///Address the connection was made to
+    pub fn ip(&self) -> SocketAddr {
+        self.ip
+    }
+
+    pub fn url(&self) -> &WebsocketUrl {
+        &self.url
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    ///Event categories this session is subscribed to
+    pub fn event_subscriptions(&self) -> OBSEventSubscription {
+        self.event_subscriptions
+    }
+
+    ///The close frame recieved from the server, if the server closed the connection
+    pub fn close_frame(&self) -> Option<&CloseFrame> {
+        self.close_frame.as_ref()
+    }
+
+    ///Closes the connection with the given status code and reason (see close_frame::NORMAL_CLOSURE).
+    ///Sends a close frame and waits up to 5 seconds for the close frame of the server before the TCP connection is shut down.
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<(), Error> {
+        if self.state == State::Closed {
+            return Ok(());
         }
-        match stream.set_write_timeout(Some(Duration::from_secs(5))) {
-            Ok(()) => (),
-            Err(error) => return Err(Websocket::error_strings(WebsocketError::SetWriteTimeoutError, Some(error.to_string()))) 
+
+        debug!("Closing connection ({code} {reason})");
+        self.state = State::Closing;
+        let result = self.send_message(CloseFrame::new(code, reason).to_message());
+        if result.is_ok() {
+            //data frames still in flight are discarded
+            let mut stream = DeadlineReader{stream: &mut self.stream, deadline: Instant::now() + TIMEOUT};
+            while let Ok(frame) = codec::read_frame(&mut stream, &self.assembler) {
+                if frame.header().get_value_opcode() == Opcode::ConnectionClose {
+                    self.close_frame = codec::close_frame(&frame).ok();
+                    break;
+                }
+            }
         }
 
-        //Upgrade connection
-        match stream.write(Websocket::request_upgrade_message(ip, sec_websocket_key.clone()).as_bytes()) {
-            Ok(_) => (),
-            Err(error) => return Err(Websocket::error_strings(WebsocketError::WriteError, Some(format!("Could not write to {}: {}", ip.to_string(), error.to_string()))))
-        };
-        match Websocket::read_upgrade_response(&mut stream, sec_websocket_key.clone()) {
-            Ok(()) => (),
-            Err(error) => return Err(error)
-        };
+        self.shutdown();
+        result
+    }
 
-        let state = State::initialising;
-        Ok(Websocket{ip, stream, state})
+    ///Time to wait for the response of a single request
+    pub fn set_request_timeout(&mut self, timeout: Duration) {
+        self.request_timeout = timeout;
     }
 
-    fn request_upgrade_message(ip: SocketAddr, sec_websocket_key: String) -> String {
-        let mut str = format!("GET / HTTP/1.1\r\n");
-        str = format!("{str}Host: {}\r\n", ip.to_string());
-        str = format!("{str}Upgrade: websocket\r\n");
-        str = format!("{str}Connection: Upgrade\r\n");
-        str = format!("{str}Sec-WebSocket-Key: {sec_websocket_key}\r\n");
-        str = format!("{str}Sec-WebSocket-Protocol: obswebsocket.json\r\n");
-        str = format!("{str}Sec-WebSocket-Version: 13\r\n");
-        str = format!("{str}\r\n");
-        println!("{}", str); //Debug
-        str
+    ///Sends a request and waits for its response.
+    ///Messages recieved in the meantime that do not belong to this request are buffered.
+    ///A response with a failed request status is returned as error, including the code and comment sent by OBS.
+    pub fn request(&mut self, request_type: &str, request_data: Value) -> Result<Payload, Error> {
+        let request_id = self.send_request(request_type, request_data)?;
+        self.wait_for_response(&request_id)?.check_request_status()
     }
 
-    fn read_upgrade_response(stream: &mut TcpStream, sec_websocket_key: String) -> Result<(), String> {
-        let mut reader = BufReader::new(stream);
+    ///Sends a typed request (see the requests module) and waits for its typed response.
+    pub fn call<R: Request>(&mut self, request: &R) -> Result<R::Response, Error> {
+        self.request(R::REQUEST_TYPE, request.request_data()?)?.typed_response()
+    }
 
-        let mut sbuffer = String::new();
+    ///Sends a request with a new unique request id and returns the id without waiting for the response.
+    ///Several requests can be in flight at the same time, their responses are collected with wait_for_response.
+    pub fn send_request(&mut self, request_type: &str, request_data: Value) -> Result<String, Error> {
+        let request_id = handshake::new_request_id();
+        let payload = Payload::opcode_request_message(request_type, &request_id, request_data);
+        debug!("Sending request {request_type} ({request_id})");
+
+        self.send_payload_message(payload)?;
+        self.requests_in_flight.insert(request_id.clone());
+        Ok(request_id)
+    }
+
+    ///Sends a request batch and waits for its response.
+    ///Returns the response of every executed request in the order the requests were added to the batch.
+    ///If the batch halts on failure, requests after the failed one are missing in the results.
+    pub fn request_batch(&mut self, batch: &RequestBatch) -> Result<Vec<Payload>, Error> {
+        let request_id = handshake::new_request_id();
+        let payload = Payload::opcode_request_batch_message(&request_id, batch);
+        debug!("Sending request batch of {} requests ({request_id})", batch.len());
+
+        self.send_payload_message(payload)?;
+        self.requests_in_flight.insert(request_id.clone());
+        Ok(self.wait_for_response(&request_id)?.batch_results())
+    }
+
+    ///Waits for the response to the request with the given id until the request timeout expires.
+    ///Responses to other requests in flight and events are buffered, responses to unknown or timed out requests are dropped.
+    pub fn wait_for_response(&mut self, request_id: &str) -> Result<Payload, Error> {
+        let buffered = self.buffered_responses.iter().position(|payload| payload.data()["requestId"] == request_id);
+        if let Some(payload) = buffered.and_then(|index| self.buffered_responses.remove(index)) {
+            return Ok(payload);
+        }
+
+        let deadline = Instant::now() + self.request_timeout;
         loop {
-            let mut sread = String::new();
-            match reader.read_line(&mut sread) {
-                Ok(_) => (),
-                Err(error) => return Err(Websocket::error_strings(WebsocketError::ReadError,Some(format!("Could not read upgrade request response: {}", error.to_string()))))
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !self.wait_for_data(remaining)? {
+                self.requests_in_flight.remove(request_id);
+                return Err(Error::websocket(WebsocketError::RequestTimeout, Some(request_id.to_string())));
             }
-            sbuffer += &sread;
 
-            if sread == "\r\n" {
-                break;
+            let payload = self.read_payload_message()?;
+            if let Some(payload) = self.dispatch(payload, Some(request_id))? {
+                return Ok(payload);
             }
         }
-
-        println!("{}", sbuffer); //Debug
-        Websocket::confirm_upgrade_response(sbuffer, sec_websocket_key)
     }
 
-    fn confirm_upgrade_response(message: String, sec_websocket_key: String) -> Result<(), String> {
-        let lines: Vec<&str> = message.split("\r\n").collect();
+    ///Changes the event subscriptions of the running session (opcode 3) and waits until OBS confirms them with Identified.
+    ///High-volume subscriptions like InputVolumeMeters can be turned on and off this way without reconnecting.
+    pub fn reidentify(&mut self, event_subscriptions: OBSEventSubscription) -> Result<(), Error> {
+        let payload = Payload::opcode_reidentify_message(event_subscriptions);
+        self.send_payload_message(payload)?;
 
-        //Check for HTTP Status
-        if lines[0] != "HTTP/1.1 101 Switching Protocols" {
-            if lines[0].starts_with("HTTP/1.1") {
-                return Err(Websocket::error_strings(WebsocketError::UpgradeError, Some(lines[0].to_string())));
+        let deadline = Instant::now() + self.request_timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !self.wait_for_data(remaining)? {
+                return Err(Error::websocket(WebsocketError::NotIdentified, Some("no Identified recieved after Reidentify".to_string())));
             }
-            else {
-                return Err(Websocket::error_strings(WebsocketError::UpgradeError, Some(message)));
+
+            let payload = self.read_payload_message()?;
+            if payload.opcode() == OBSOpcode::Identifyed {
+                self.event_subscriptions = event_subscriptions;
+                return Ok(());
             }
-            
+            self.dispatch(payload, None)?;
         }
+    }
 
-        match Websocket::check_upgrade_response_values(lines, sec_websocket_key) {
-            Ok(()) => Ok(()),
-            Err(error) => Err(error)
+    ///Blocks until the next event arrives. Responses recieved in the meantime are buffered for their requests.
+    pub fn next_event(&mut self) -> Result<Event, Error> {
+        loop {
+            if let Some(event) = self.next_event_timeout(TIMEOUT)? {
+                return Ok(event);
+            }
         }
     }
 
-    fn check_upgrade_response_values(lines: Vec<&str>, sec_websocket_key: String) -> Result<(), String> {
-        let mut websocket = false;
-        let mut upgrade = false;
-        let mut accept = false;
-        let mut protocol = false;
-
-        for line in &lines[1..] {
-            match line {
-                &"Upgrade: websocket" => {websocket = true; continue;},
-                &"Connection: Upgrade" => {upgrade = true; continue;},
-                _ => ()
-            }
-            if line.starts_with("Sec-WebSocket-Accept:") {
-                accept = Websocket::check_sec_websocket_accept(line.replace("Sec-WebSocket-Accept: ", ""), sec_websocket_key.clone());
-                continue;
+    ///Waits for the next event until the timeout expires. Returns None if no event arrived.
+    pub fn next_event_timeout(&mut self, timeout: Duration) -> Result<Option<Event>, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(event) = self.buffered_events.pop_front() {
+                return Ok(Some(event));
             }
-            if line.starts_with("Sec-WebSocket-Protocol:") {
-                if line.replace("Sec-WebSocket-Protocol: ", "") == "obswebsocket.json" {
-                    protocol = true;
-                }
-                continue;
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || !self.wait_for_data(remaining)? {
+                return Ok(None);
             }
-        }
 
-        if !websocket {
-            return Err(Websocket::error_strings(WebsocketError::UpgradeTypeError, None));
-        }
-        if !upgrade {
-            return Err(Websocket::error_strings(WebsocketError::UpgradeConncetionError, None));
-        }
-        if !accept {
-            return Err(Websocket::error_strings(WebsocketError::UpgradeAcceptError, None));
-        }
-        if !protocol {
-            return Err(Websocket::error_strings(WebsocketError::UpgradeProtocolError, None));
+            let payload = self.read_payload_message()?;
+            self.dispatch(payload, None)?;
         }
+    }
 
-        Ok(())
+    ///Blocking iterator over all events, ends when the connection is closed
+    pub fn events(&mut self) -> Events<'_> {
+        Events::new(self)
+    }
+
+    ///Registers a callback that is called for every event as soon as it is recieved,
+    ///no matter if the websocket is waiting for a response or an event at that moment.
+    ///Events are still available through next_event afterwards.
+    pub fn on_event<F: FnMut(&Event) + Send + 'static>(&mut self, callback: F) {
+        self.event_callbacks.push(Box::new(callback));
+    }
+
+    pub fn has_buffered_events(&self) -> bool {
+        !self.buffered_events.is_empty()
     }
-    
-    fn new_sec_websocket_key() -> String {
-        let mut random =  [0u8; 16];
-        rand::thread_rng().fill_bytes(&mut random);
-        general_purpose::STANDARD.encode(random)
-    }
-
-    fn check_sec_websocket_accept(sec_websocket_accept: String, sec_websocket_key: String) -> bool {
-        //recreate supposed sec_websocket_accept value
-        let fixed = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11".to_string();
-        let mut check_value = format!("{}{}", sec_websocket_key, fixed);
-        let mut hasher = Sha1::new();
-        hasher.update(check_value);
-        check_value = general_purpose::STANDARD.encode(hasher.finalize());
-
-        sec_websocket_accept == check_value
-    }
-
-    fn opcode1_payload(rpcversion: usize, password: Option<String>, event_subscriptions: OBSEventSubscription, opcode0_message: Payload) -> Result<String, String> {
-        let mut auth_required = false;
-        let mut challenge = String::new();
-        let mut salt = String::new();
-        let mut rpcversion_supported = false;
-        let mut requested_rpcversion = String::new();
-        
-
-        for (key, value) in opcode0_message.attributes() {
-            match key.as_str() {
-                "rpcVersion" => {rpcversion_supported = Websocket::rpcVersion_supported(value.clone(), rpcversion); requested_rpcversion = value},
-                "challenge" => {challenge = value; auth_required = true},
-                "salt" => {salt = value; auth_required = true},
-                _ => ()
+
+    ///Sorts a recieved message into events and responses.
+    ///Returns the payload if it is the response to the given request id.
+    fn dispatch(&mut self, payload: Payload, request_id: Option<&str>) -> Result<Option<Payload>, Error> {
+        match payload.route()? {
+            Route::Event(event) => {
+                for callback in self.event_callbacks.iter_mut() {
+                    callback(&event);
+                }
+                if self.buffered_events.len() >= MAX_BUFFERED_EVENTS {
+                    warn!("Event buffer full, dropping the oldest event");
+                    self.buffered_events.pop_front();
+                }
+                self.buffered_events.push_back(event);
+                Ok(None)
+            },
+            Route::Response(id, payload) => {
+                if !self.requests_in_flight.remove(&id) {
+                    //response to an unknown or timed out request
+                    warn!("Dropping response to unknown request {id}");
+                    return Ok(None);
+                }
+                debug!("Recieved response to {id}");
+
+                if request_id == Some(id.as_str()) {
+                    Ok(Some(payload))
+                }
+                else {
+                    self.buffered_responses.push_back(payload);
+                    Ok(None)
+                }
+            },
+            Route::Other(payload) => {
+                debug!("Ignoring message with opcode {:?}", payload.opcode());
+                Ok(None)
             }
         }
+    }
 
-        if !rpcversion_supported {
-            return Err(Websocket::error_strings(WebsocketError::RpcVersionNotSupported, Some(format!("requested {requested_rpcversion}, supported {rpcversion}"))));
+    ///Waits until data is available on the connection. Returns false if the timeout expired.
+    fn wait_for_data(&mut self, timeout: Duration) -> Result<bool, Error> {
+        //a timeout of zero is not allowed
+        let timeout = timeout.max(Duration::from_millis(1));
+        if let Err(error) = self.stream.set_read_timeout(Some(timeout)) {
+            return Err(Error::io(WebsocketError::SetReadTimeoutError, error));
         }
 
-        if auth_required {
-            let authentication = match password {
-                Some(password) => Websocket::create_authentication_string(password, challenge, salt),
-                None => return Err(Websocket::error_strings(WebsocketError::AuthenticationRequired, None))
-            };
-            Ok(Payload::opcode_identify_message(1, Some(authentication), event_subscriptions))
+        let result = self.stream.has_data();
+
+        if let Err(error) = self.stream.set_read_timeout(Some(TIMEOUT)) {
+            return Err(Error::io(WebsocketError::SetReadTimeoutError, error));
         }
-        else {
-            Ok(Payload::opcode_identify_message(rpcversion, None, event_subscriptions))
+        match result {
+            Ok(false) => {
+                self.shutdown();
+                Err(self.closed_error())
+            },
+            Ok(true) => Ok(true),
+            Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(false),
+            Err(error) => Err(Error::io(WebsocketError::ReadError, error))
         }
-        
-        
     }
 
-    fn create_authentication_string(password: String, challenge: String, salt: String) -> String {
-        let secret = Websocket::create_base64_secret(format!("{password}{salt}"));
-        Websocket::create_base64_secret(format!("{secret}{challenge}"))
+    ///Starts tracking pings and pongs. Pongs recieved while reading messages are recorded in the heartbeat.
+    pub fn enable_heartbeat(&mut self) {
+        if self.heartbeat.is_none() {
+            self.heartbeat = Some(Heartbeat::new());
+        }
     }
 
-    fn create_base64_secret(text: String) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(text);
-        let hash = hasher.finalize();
-        general_purpose::STANDARD.encode(hash)
+    pub fn heartbeat(&self) -> Option<&Heartbeat> {
+        self.heartbeat.as_ref()
     }
 
-    fn rpcVersion_supported(rpcVersion: String, supported: usize) -> bool {
-        let rpcv: usize = match rpcVersion.parse() {
-            Ok(rpcv) => rpcv,
-            Err(error) => return false
-        };
-
-        rpcv == supported
+    ///Sends a ping to the server. The answering pong is recorded in the heartbeat if enabled.
+    pub fn send_ping(&mut self) -> Result<(), Error> {
+        self.send_message(Message::new(true, Opcode::Ping, true, String::new()))?;
+        if let Some(heartbeat) = self.heartbeat.as_mut() {
+            heartbeat.ping_sent();
+        }
+        Ok(())
     }
-    //-----------
 
-    pub fn send_message(&mut self, message: Message) -> Result<(), String> {
-        match self.stream.write(&message.to_bytes()) {
-            Ok(_) => Ok(()),
-            Err(error) => Err(Websocket::error_strings(WebsocketError::WriteError, Some(format!("Could not write to Websocket: {}", error.to_string()))))
-        }
+    //initialise
+    fn initialise_websocket(url: WebsocketUrl, ip: SocketAddr, mut stream: Stream, options: &ConnectOptions) -> Result<Websocket, Error> {
+        let deflate = Websocket::upgrade(&url, &mut stream, options)?;
+
+        let state = State::Initialising;
+        Ok(Websocket{
+            url, ip, stream, state, deflate,
+            encoding: options.encoding,
+            assembler: Assembler::new(),
+            heartbeat: None,
+            close_frame: None,
+            request_timeout: TIMEOUT,
+            requests_in_flight: HashSet::new(),
+            buffered_responses: VecDeque::new(),
+            buffered_events: VecDeque::new(),
+            event_callbacks: Vec::new(),
+            event_subscriptions: OBSEventSubscription::None
+        })
     }
 
-    pub fn read_message(&mut self) -> Result<Message, String> {
-        //read header
-        let mut bytes = match self.read_header() {
-            Ok(bytes) => bytes,
-            Err(error) => return Err(error)
-        };
+    //Upgrade connection, returns the compression if the server agreed to permessage-deflate
+    pub(crate) fn upgrade(url: &WebsocketUrl, stream: &mut Stream, options: &ConnectOptions) -> Result<Option<Deflate>, Error> {
+        let sec_websocket_key = handshake::new_sec_websocket_key();
+        let extensions = handshake::offer_extensions(options)?;
 
-        //read mask if set
-        let header = match Header::from_bytes(bytes.clone()) {
-            Ok(header) => header,
-            Err(error) => return Err(Header::error_strings(error))
+        let encoding = options.encoding;
+        match stream.write_all(handshake::request_upgrade_message(url, sec_websocket_key.clone(), encoding, extensions.as_deref()).as_bytes()) {
+            Ok(()) => (),
+            Err(error) => return Err(Error::io(WebsocketError::WriteError, error))
         };
-        if header.has_mask_byte_set() {
-            match self.read_mask() {
-                Ok(mask) => bytes.extend(mask.into_iter()),
-                Err(error) => return Err(error)
-            };
+        let accepted_extensions = Websocket::read_upgrade_response(stream, sec_websocket_key, encoding)?;
+        handshake::accept_extensions(options, accepted_extensions)
+    }
+
+    //tries the resolved addresses in order and returns the first connection, or the error of the last address
+    fn connect_tcp(url: &WebsocketUrl) -> Result<(SocketAddr, TcpStream), Error> {
+        let addresses = url.resolve()?;
+        let mut last_error = io::Error::new(ErrorKind::NotFound, format!("{} did not resolve to any address", url.host()));
+        for address in addresses {
+            debug!("Connecting to {address}");
+            match TcpStream::connect_timeout(&address, TIMEOUT) {
+                Ok(stream) => return Websocket::set_timeouts(&stream).map(|()| (address, stream)),
+                Err(error) => {
+                    debug!("Could not connect to {address}: {error}");
+                    last_error = error;
+                }
+            }
         }
-        
-        //read payload
-        match self.read_payload(header.get_value_payload_length()) {
-            Ok(payload) => bytes.extend(payload.iter()),
-            Err(error) => return Err(error)
-        };
-        
-        //parse message
-        Ok(match Message::from_bytes(bytes) {
-            Ok(message) => message,
-            Err(error) => return Err(error)
-        })
+        Err(Error::io(WebsocketError::ConnectError, last_error))
     }
 
-    fn read_header(&mut self) -> Result<Vec<u8>, String> {
-        let mut header: Vec<u8> = Vec::new();
-        let mut buffer = [0u8; 2];
-        match self.read(&mut buffer) {
-            Ok(buffer) => header.extend(buffer.iter()),
-            Err(error) => return Err(error)
+    fn set_timeouts(stream: &TcpStream) -> Result<(), Error> {
+        match stream.set_read_timeout(Some(TIMEOUT)) {
+            Ok(()) => (),
+            Err(error) => return Err(Error::io(WebsocketError::SetReadTimeoutError, error))
         }
+        match stream.set_write_timeout(Some(TIMEOUT)) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(Error::io(WebsocketError::SetWriteTimeoutError, error))
+        }
+    }
 
-        //check for extended header size
-        let header_size = Header::required_header_size(buffer.clone());
-        if header_size != 2 {
-            if header_size == 4 {
-                let extra = [0u8; 2];
-                match self.read(&mut buffer) {
-                    Ok(buffer) => header.extend(buffer.iter()),
-                    Err(error) => return Err(error)
-                }
+    //reads byte by byte up to the empty line, a frame sent right after the response (Hello) stays in the stream
+    //returns the Sec-WebSocket-Extensions accepted by the server
+    fn read_upgrade_response(stream: &mut Stream, sec_websocket_key: String, encoding: Encoding) -> Result<Option<String>, Error> {
+        let mut response = Vec::new();
+        let mut byte = [0u8; 1];
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() >= handshake::MAX_UPGRADE_RESPONSE {
+                return Err(Error::websocket(WebsocketError::UpgradeError, Some("upgrade response too long".to_string())));
             }
-            else if header_size == 10 {
-                let extra = [0u8; 8];
-                match self.read(&mut buffer) {
-                    Ok(buffer) => header.extend(buffer.iter()),
-                    Err(error) => return Err(error)
-                }
+            match stream.read_exact(&mut byte) {
+                Ok(()) => response.push(byte[0]),
+                Err(error) => return Err(Error::io(WebsocketError::ReadError, error))
             }
         }
 
-        Ok(header)
+        let sbuffer = String::from_utf8_lossy(&response).to_string();
+        debug!("Upgrade response:\n{}", sbuffer.trim_end());
+        handshake::confirm_upgrade_response(sbuffer, sec_websocket_key, encoding)
     }
 
-    fn read_mask(&mut self) -> Result<Vec<u8>, String> {
-        let mut mask: Vec<u8> = Vec::new();
-        let mut buffer = [0u8; 4];
-        match self.read(&mut buffer) {
-            Ok(buffer) => mask.extend(buffer.iter()),
-            Err(error) => return Err(error)
+    //-----------
+
+    ///Sends a frame. Complete text and binary messages are compressed if permessage-deflate was negotiated.
+    pub fn send_message(&mut self, message: Message) -> Result<(), Error> {
+        let bytes = codec::frame_bytes(message, self.deflate.as_mut())?;
+        match self.stream.write_all(&bytes).and_then(|()| self.stream.flush()) {
+            Ok(()) => Ok(()),
+            Err(error) => Err(Error::io(WebsocketError::WriteError, error))
         }
+    }
+
+    //sends a message built by the Payload::opcode_*_message functions in the negotiated encoding
+    fn send_payload_message(&mut self, payload: Value) -> Result<(), Error> {
+        let message = self.encoding.encode(&payload)?;
+        self.send_message(message)
+    }
 
-        Ok(mask)
+    fn read_payload_message(&mut self) -> Result<Payload, Error> {
+        let message = self.read_message()?;
+        encoding::decode(&message)
     }
 
-    fn read_payload(&mut self, payload_length: u64) -> Result<Vec<u8>, String> {
-        let mut payload: Vec<u8> = Vec::new();
-        for _ in 0..payload_length {
-            let mut byte = [0u8; 1];
-            match self.read(&mut byte) {
-                Ok(byte) => payload.push(byte[0]),
-                Err(error) => return Err(error)
-            };
+    ///Reads the next data message from the websocket.
+    ///Fragmented messages are reassembled from their continuation frames until the frame with the fin bit set arrives.
+    ///Control frames may arrive in between the fragments of a message and are handled transparently:
+    ///pings are answered with a pong, pongs are passed to the heartbeat and a close frame ends the connection.
+    ///A text message that is not valid UTF-8 fails the connection with status code 1007.
+    pub fn read_message(&mut self) -> Result<Message, Error> {
+        if self.state == State::Closed {
+            return Err(self.closed_error());
         }
 
-        Ok(payload)
+        match self.next_message() {
+            Ok(message) => Ok(message),
+            Err(violation) => {
+                if let Some((code, reason)) = violation.close {
+                    let _ = self.close(code, reason);
+                }
+                Err(violation.error)
+            }
+        }
     }
 
-    fn read<'a>(&'a mut self, buffer: &'a mut [u8]) -> Result<&[u8], String> {
-        match self.stream.read(buffer) {
-            Ok(_) => Ok(buffer),
-            Err(error) => Err (Websocket::error_strings(WebsocketError::ReadError, Some(error.to_string())))
+    fn next_message(&mut self) -> Result<Message, Violation> {
+        loop {
+            let frame = codec::read_frame(&mut self.stream, &self.assembler)?;
+            if frame.header().is_control_frame() {
+                self.handle_control_frame(frame)?;
+                continue;
+            }
+
+            if let Some(message) = self.assembler.push(frame, self.deflate.as_mut())? {
+                return Ok(message);
+            }
         }
     }
 
-    //debug
-    /*fn read_close(&mut self) -> Result<String, String> {
-        let mut message: Vec<char> = Vec::new();
-        loop {
-            let mut byte = [0u8; 1];
-            match self.read(&mut byte) {
-                Ok(byte) => message.push(byte[0] as char),
-                Err(_) => break
-            };
-            print!("{}", byte[0] as char);
-            if(message.len() >= 4) {
-                if format!("{}{}", message[message.len()-1], message[message.len()-2]) == "\r\n" && format!("{}{}",message[message.len()-3],message[message.len()-4]) == "\r\n" {
-                    break;
+    fn handle_control_frame(&mut self, frame: Message) -> Result<(), Violation> {
+        match codec::control_frame(&frame, self.state == State::Closing)? {
+            Control::Ping(pong) => Ok(self.send_message(pong)?),
+            Control::Pong => {
+                if let Some(heartbeat) = self.heartbeat.as_mut() {
+                    heartbeat.pong_recieved();
+                }
+                Ok(())
+            },
+            Control::Close{close_frame, reply} => {
+                if let Some(reply) = reply {
+                    let _ = self.send_message(reply);
                 }
+                self.close_frame = Some(close_frame);
+                self.shutdown();
+                Err(self.closed_error().into())
             }
         }
+    }
+
+    fn shutdown(&mut self) {
+        self.stream.shutdown();
+        self.state = State::Closed;
+    }
 
-        Ok(String::from_iter(message))
-    }*/
+    fn closed_error(&self) -> Error {
+        Error::closed(self.close_frame.clone())
+    }
+
+}
+
+//reads until the deadline, every read waits at most for the time that is left
+struct DeadlineReader<'a> {
+    stream: &'a mut Stream,
+    deadline: Instant
+}
 
-    fn error_strings(error: WebsocketError, informations: Option<String>) -> String {
-        let string = match error {
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(ErrorKind::TimedOut, "deadline expired"));
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        self.stream.read(buffer)
+    }
+}
+
+impl fmt::Display for WebsocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WebsocketError::InvalidUrl => "Invalid WebSocket url",
+            WebsocketError::ResolveError => "Could not resolve WebSocket server host name",
+            WebsocketError::TlsHandshakeError => "TLS handshake failed",
+            WebsocketError::TlsCertificateError => "Invalid TLS certificate settings",
             WebsocketError::ConnectError => "Error while connecting to WebSocket server",
             WebsocketError::SetReadTimeoutError => "Could not set connection read timeout",
             WebsocketError::SetWriteTimeoutError => "Could not set connection write timeout",
@@ -421,15 +641,25 @@ impl Websocket {
             WebsocketError::UpgradeConncetionError => "Could not upgrade connection. Upgrade connection indicator wrong/not found",
             WebsocketError::UpgradeAcceptError => "Could not upgrade connection. Upgrade accept value wrong/not found",
             WebsocketError::UpgradeProtocolError => "Could not upgrade connection. Upgrade protocol wrong/ not found",
+            WebsocketError::UpgradeExtensionError => "Could not upgrade connection. The server accepted an extension that was not offered or with invalid parameters",
+            WebsocketError::DeflateError => "Could not compress or decompress a message",
             WebsocketError::MissingOpCode0 => "Wrong Message recieved. Expected Opcode 0",
             WebsocketError::RpcVersionNotSupported => "The submitted RpcVersion is not supported",
             WebsocketError::AuthenticationRequired => "This OBS WebSocket requires authentication",
-            WebsocketError::NotIdentified => "Could not authenticate"
-        }.to_string();
-        match informations {
-            Some(info) => format!("{}: {}", string, info),
-            None => string
-        }
+            WebsocketError::NotIdentified => "Could not authenticate",
+            WebsocketError::FragmentationError => "Recieved an invalid sequence of message fragments",
+            WebsocketError::ControlFrameError => "Recieved an invalid control frame (fragmented or payload larger than 125 bytes)",
+            WebsocketError::MessageTooBig => "Recieved a frame or message larger than the size limit",
+            WebsocketError::NotConnected => "The connection is closed",
+            WebsocketError::RequestTimeout => "No response recieved for request"
+        })
     }
 }
 
+impl Drop for Websocket {
+    fn drop(&mut self) {
+        if self.state == State::Open {
+            let _ = self.close(crate::close_frame::NORMAL_CLOSURE, "");
+        }
+    }
+}
@@ -0,0 +1,119 @@
+#![cfg(feature = "async")]
+//AsyncWebsocket against a loopback server: concurrent requests, timeouts and the reader task
+
+mod common;
+
+use std::{net::SocketAddr, time::Duration};
+
+use serde_json::json;
+
+use obs_remote_1lt::async_websocket::AsyncWebsocket;
+use obs_remote_1lt::close_frame::NORMAL_CLOSURE;
+use obs_remote_1lt::error::{Error, WebsocketError};
+use obs_remote_1lt::event::Event;
+use obs_remote_1lt::obs::{OBSEventSubscription, OBSWebSocketCloseCode};
+use obs_remote_1lt::websocket::State;
+
+async fn connect(address: SocketAddr) -> AsyncWebsocket {
+    AsyncWebsocket::connect(&format!("ws://{address}"), None, 1, OBSEventSubscription::All).await.unwrap()
+}
+
+#[tokio::test]
+async fn concurrent_requests_are_matched_by_request_id() {
+    let (address, server) = common::serve(|stream| {
+        let requests: Vec<_> = (0..3).map(|_| common::read_json(stream)).collect();
+        for request in requests.iter().rev() {
+            common::respond(stream, request);
+        }
+        common::close(stream)
+    });
+    let obs = connect(address).await;
+
+    let (version, scenes, inputs) = tokio::join!(
+        obs.request("GetVersion", json!({})),
+        obs.request("GetSceneList", json!({})),
+        obs.request("GetInputList", json!({})));
+    assert_eq!(version.unwrap().response_data()["requestType"], "GetVersion");
+    assert_eq!(scenes.unwrap().response_data()["requestType"], "GetSceneList");
+    assert_eq!(inputs.unwrap().response_data()["requestType"], "GetInputList");
+
+    obs.close(NORMAL_CLOSURE, "").await.unwrap();
+    assert_eq!(server.join().unwrap(), Some(NORMAL_CLOSURE));
+    let error = obs.request("GetVersion", json!({})).await.err().unwrap();
+    assert!(matches!(error, Error::ConnectionClosed(_)), "{error}");
+}
+
+#[tokio::test]
+async fn request_times_out_and_its_late_response_is_dropped() {
+    let (address, server) = common::serve(|stream| {
+        let late = common::read_json(stream);
+        let next = common::read_json(stream);
+        common::respond(stream, &late);
+        common::respond(stream, &next);
+        common::close(stream)
+    });
+    let mut obs = connect(address).await;
+
+    obs.set_request_timeout(Duration::from_millis(200));
+    let error = obs.request("GetVersion", json!({})).await.err().unwrap();
+    assert!(matches!(error, Error::Websocket{kind: WebsocketError::RequestTimeout, ..}), "{error}");
+
+    obs.set_request_timeout(Duration::from_secs(5));
+    assert_eq!(obs.request("GetSceneList", json!({})).await.unwrap().response_data()["requestType"], "GetSceneList");
+    obs.close(NORMAL_CLOSURE, "").await.unwrap();
+    assert_eq!(server.join().unwrap(), Some(NORMAL_CLOSURE));
+}
+
+#[tokio::test]
+async fn requests_in_flight_fail_when_the_server_closes() {
+    let (address, server) = common::serve(|stream| {
+        common::read_json(stream);
+        common::read_json(stream);
+        let mut payload = 4011u16.to_be_bytes().to_vec();
+        payload.extend_from_slice(b"kicked");
+        common::send_frame(stream, 0x88, &payload);
+        common::read_frame(stream)
+    });
+    let obs = connect(address).await;
+    let mut events = obs.events();
+
+    let (version, scenes) = tokio::join!(obs.request("GetVersion", json!({})), obs.request("GetSceneList", json!({})));
+    for result in [version, scenes] {
+        let error = result.err().unwrap();
+        assert_eq!(error.close_code(), Some(OBSWebSocketCloseCode::SessionInvalidated), "{error}");
+    }
+
+    assert!(events.recv().await.is_none());
+    assert_eq!(obs.state(), State::Closed);
+    assert_eq!(obs.close_frame().unwrap().reason(), "kicked");
+    let (opcode, payload) = server.join().unwrap();
+    assert_eq!((opcode, &payload[..2]), (0x08, &4011u16.to_be_bytes()[..]));
+}
+
+#[tokio::test]
+async fn full_event_stream_drops_new_events() {
+    let (address, server) = common::serve(|stream| {
+        let request = common::read_json(stream);
+        for index in 0..1030 {
+            common::send_event(stream, &index.to_string());
+        }
+        common::respond(stream, &request);
+        common::close(stream)
+    });
+    let obs = connect(address).await;
+    let mut events = obs.events();
+
+    //the response is read after every event was handed out
+    obs.request("GetVersion", json!({})).await.unwrap();
+    obs.close(NORMAL_CLOSURE, "").await.unwrap();
+    let mut scenes = Vec::new();
+    while let Some(event) = events.recv().await {
+        match event {
+            Event::CurrentProgramSceneChanged{scene_name, ..} => scenes.push(scene_name),
+            event => panic!("unexpected event {}", event.event_type())
+        }
+    }
+    assert_eq!(scenes.len(), 1024);
+    assert_eq!((scenes[0].as_str(), scenes[1023].as_str()), ("0", "1023"));
+    assert_eq!(server.join().unwrap(), Some(NORMAL_CLOSURE));
+}
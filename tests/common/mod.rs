@@ -0,0 +1,115 @@
+//Loopback OBS server for the client tests
+//every test crate includes this module, but not every test uses every helper
+#![allow(dead_code)]
+
+use std::{io::{Read, Write}, net::{SocketAddr, TcpListener, TcpStream}, thread::{self, JoinHandle}};
+
+use base64::{Engine as _, engine::general_purpose};
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+
+//accepts one connection and runs server on it once it is identified
+pub fn serve<T: Send + 'static>(server: impl FnOnce(&mut TcpStream) -> T + Send + 'static) -> (SocketAddr, JoinHandle<T>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let handle = thread::spawn(move || server(&mut accept(&listener)));
+    (address, handle)
+}
+
+//accepts the next connection and identifies it without authentication
+pub fn accept(listener: &TcpListener) -> TcpStream {
+    let mut stream = upgrade(listener);
+    send_text(&mut stream, &json!({"op": 0, "d": {"obsWebSocketVersion": "5.1.0", "rpcVersion": 1}}));
+    read_frame(&mut stream);
+    send_text(&mut stream, &json!({"op": 2, "d": {"negotiatedRpcVersion": 1}}));
+    stream
+}
+
+//accepts the next connection and completes the websocket handshake, before the Hello
+pub fn upgrade(listener: &TcpListener) -> TcpStream {
+    let (mut stream, _) = listener.accept().unwrap();
+
+    let mut request = Vec::new();
+    let mut byte = [0u8; 1];
+    while !request.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        request.push(byte[0]);
+    }
+    let request = String::from_utf8(request).unwrap();
+    let key = request.lines().find_map(|line| line.strip_prefix("Sec-WebSocket-Key: ")).unwrap();
+    let accept = general_purpose::STANDARD.encode(Sha1::digest(format!("{key}258EAFA5-E914-47DA-95CA-C5AB0DC85B11")));
+    let response = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {accept}\r\nSec-WebSocket-Protocol: obswebsocket.json\r\n\r\n");
+    stream.write_all(response.as_bytes()).unwrap();
+    stream
+}
+
+//answers a request successfully with its request type as response data
+pub fn respond(stream: &mut TcpStream, request: &Value) {
+    send_text(stream, &json!({"op": 7, "d": {
+        "requestType": request["d"]["requestType"],
+        "requestId": request["d"]["requestId"],
+        "requestStatus": {"result": true, "code": 100},
+        "responseData": {"requestType": request["d"]["requestType"]}
+    }}));
+}
+
+//sends a CurrentProgramSceneChanged event
+pub fn send_event(stream: &mut TcpStream, scene: &str) {
+    send_text(stream, &json!({"op": 5, "d": {"eventType": "CurrentProgramSceneChanged", "eventIntent": 4, "eventData": {"sceneName": scene}}}));
+}
+
+pub fn read_json(stream: &mut TcpStream) -> Value {
+    serde_json::from_slice(&read_frame(stream).1).unwrap()
+}
+
+//waits for the close frame of the client and answers it, returns its status code
+pub fn close(stream: &mut TcpStream) -> Option<u16> {
+    let (opcode, payload) = read_frame(stream);
+    stream.write_all(&[0x88, 0]).unwrap();
+    (opcode == 8 && payload.len() >= 2).then(|| u16::from_be_bytes([payload[0], payload[1]]))
+}
+
+pub fn send_text(stream: &mut TcpStream, message: &Value) {
+    send_frame(stream, 0x81, message.to_string().as_bytes());
+}
+
+//sends an unmasked frame, first is the byte with the fin bit, RSV bits and opcode
+pub fn send_frame(stream: &mut TcpStream, first: u8, payload: &[u8]) {
+    let mut frame = vec![first];
+    match payload.len() {
+        length @ 0..=125 => frame.push(length as u8),
+        length @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        },
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame).unwrap();
+}
+
+//reads a masked frame, returns its opcode and payload
+pub fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).unwrap();
+    let length = match header[1] & 0x7f {
+        126 => {
+            let mut length = [0u8; 2];
+            stream.read_exact(&mut length).unwrap();
+            u16::from_be_bytes(length) as usize
+        },
+        127 => {
+            let mut length = [0u8; 8];
+            stream.read_exact(&mut length).unwrap();
+            u64::from_be_bytes(length) as usize
+        },
+        length => length as usize
+    };
+    let mut masked = vec![0u8; 4 + length];
+    stream.read_exact(&mut masked).unwrap();
+    let payload = masked[4..].iter().enumerate().map(|(index, byte)| byte ^ masked[index % 4]).collect();
+    (header[0] & 0x0f, payload)
+}
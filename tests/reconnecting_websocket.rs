@@ -0,0 +1,124 @@
+//ReconnectingWebsocket against a loopback server that drops connections, ignores pings or refuses the session
+
+mod common;
+
+use std::{io::Read, net::TcpListener, thread, time::{Duration, Instant}};
+
+use serde_json::json;
+
+use obs_remote_1lt::close_frame::NORMAL_CLOSURE;
+use obs_remote_1lt::connect_options::ConnectOptions;
+use obs_remote_1lt::obs::OBSEventSubscription;
+use obs_remote_1lt::reconnecting_websocket::{ConnectionEvent, ReconnectOptions, ReconnectingWebsocket};
+use obs_remote_1lt::url::WebsocketUrl;
+use obs_remote_1lt::websocket::State;
+
+fn bind() -> (TcpListener, WebsocketUrl) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = WebsocketUrl::parse(&format!("ws://{}", listener.local_addr().unwrap())).unwrap();
+    (listener, url)
+}
+
+#[test]
+fn reconnects_after_the_connection_was_lost() {
+    let (listener, url) = bind();
+    let server = thread::spawn(move || {
+        //ends the first connection without answering the request
+        let mut stream = common::accept(&listener);
+        common::read_json(&mut stream);
+        drop(stream);
+
+        let mut stream = common::accept(&listener);
+        let request = common::read_json(&mut stream);
+        common::respond(&mut stream, &request);
+        common::close(&mut stream)
+    });
+
+    let reconnect = ReconnectOptions::new().delay(Duration::from_millis(10), Duration::from_millis(100));
+    let obs = ReconnectingWebsocket::connect_with(url, &ConnectOptions::new(), reconnect, None, 1, OBSEventSubscription::None).unwrap();
+    let connection_events = obs.connection_events();
+
+    assert!(obs.request("GetVersion", json!({})).is_err());
+    assert!(matches!(connection_events.recv().unwrap(), ConnectionEvent::Disconnected{..}));
+    assert!(matches!(connection_events.recv().unwrap(), ConnectionEvent::Reconnecting{attempt: 1, ..}));
+    assert_eq!(connection_events.recv().unwrap(), ConnectionEvent::Reconnected);
+    assert_eq!(obs.request("GetVersion", json!({})).unwrap().response_data()["requestType"], "GetVersion");
+
+    obs.close(NORMAL_CLOSURE, "").unwrap();
+    assert_eq!(server.join().unwrap(), Some(NORMAL_CLOSURE));
+    assert_eq!(obs.state(), State::Closed);
+    assert!(connection_events.recv().is_err());
+}
+
+#[test]
+fn close_stops_reconnecting() {
+    let (listener, url) = bind();
+    let server = thread::spawn(move || drop(common::accept(&listener)));
+
+    let reconnect = ReconnectOptions::new().delay(Duration::from_secs(30), Duration::from_secs(30));
+    let obs = ReconnectingWebsocket::connect_with(url, &ConnectOptions::new(), reconnect, None, 1, OBSEventSubscription::None).unwrap();
+    let connection_events = obs.connection_events();
+    server.join().unwrap();
+    assert!(matches!(connection_events.recv().unwrap(), ConnectionEvent::Disconnected{..}));
+    assert!(matches!(connection_events.recv().unwrap(), ConnectionEvent::Reconnecting{attempt: 1, ..}));
+    assert_eq!(obs.state(), State::Initialising);
+
+    let closing = Instant::now();
+    obs.close(NORMAL_CLOSURE, "").unwrap();
+    assert!(closing.elapsed() < Duration::from_secs(1), "{:?}", closing.elapsed());
+    assert_eq!(obs.state(), State::Closed);
+    assert!(obs.connection().is_none());
+    assert!(connection_events.recv().is_err());
+}
+
+#[test]
+fn connection_is_lost_without_pong() {
+    let (listener, url) = bind();
+    let server = thread::spawn(move || {
+        //reads the pings without answering them until the client aborts the connection
+        let mut stream = common::accept(&listener);
+        let mut buffer = [0u8; 256];
+        while matches!(stream.read(&mut buffer), Ok(length) if length > 0) {}
+    });
+
+    let reconnect = ReconnectOptions::new()
+        .delay(Duration::from_secs(30), Duration::from_secs(30))
+        .heartbeat(Duration::from_millis(50), Duration::from_millis(200));
+    let obs = ReconnectingWebsocket::connect_with(url, &ConnectOptions::new(), reconnect, None, 1, OBSEventSubscription::None).unwrap();
+    let connection_events = obs.connection_events();
+
+    match connection_events.recv_timeout(Duration::from_secs(5)).unwrap() {
+        ConnectionEvent::Disconnected{reason} => assert!(reason.starts_with("no pong"), "{reason}"),
+        connection_event => panic!("unexpected {connection_event:?}")
+    }
+    server.join().unwrap();
+    obs.close(NORMAL_CLOSURE, "").unwrap();
+}
+
+#[test]
+fn gives_up_when_the_authentication_fails() {
+    let (listener, url) = bind();
+    let server = thread::spawn(move || {
+        drop(common::accept(&listener));
+
+        let mut stream = common::upgrade(&listener);
+        common::send_text(&mut stream, &json!({"op": 0, "d": {"obsWebSocketVersion": "5.1.0", "rpcVersion": 1}}));
+        common::read_json(&mut stream);
+        let mut payload = 4009u16.to_be_bytes().to_vec();
+        payload.extend_from_slice(b"Authentication failed.");
+        common::send_frame(&mut stream, 0x88, &payload);
+        common::read_frame(&mut stream).0
+    });
+
+    let reconnect = ReconnectOptions::new().delay(Duration::from_millis(10), Duration::from_millis(100));
+    let obs = ReconnectingWebsocket::connect_with(url, &ConnectOptions::new(), reconnect, None, 1, OBSEventSubscription::None).unwrap();
+    let connection_events = obs.connection_events();
+
+    assert!(matches!(connection_events.recv().unwrap(), ConnectionEvent::Disconnected{..}));
+    assert!(matches!(connection_events.recv().unwrap(), ConnectionEvent::Reconnecting{attempt: 1, ..}));
+    assert!(matches!(connection_events.recv().unwrap(), ConnectionEvent::ReconnectFailed{attempt: 1, ..}));
+    assert!(matches!(connection_events.recv().unwrap(), ConnectionEvent::GaveUp{..}));
+    assert!(connection_events.recv().is_err());
+    assert_eq!(obs.state(), State::Closed);
+    assert_eq!(server.join().unwrap(), 0x08);
+}
@@ -0,0 +1,121 @@
+//SharedWebsocket against a loopback server: requests from several threads, timeouts and the reader thread
+
+mod common;
+
+use std::{net::SocketAddr, sync::mpsc::TryRecvError, thread, time::Duration};
+
+use serde_json::json;
+
+use obs_remote_1lt::close_frame::NORMAL_CLOSURE;
+use obs_remote_1lt::error::{Error, WebsocketError};
+use obs_remote_1lt::event::Event;
+use obs_remote_1lt::obs::{OBSEventSubscription, OBSWebSocketCloseCode};
+use obs_remote_1lt::shared_websocket::SharedWebsocket;
+use obs_remote_1lt::websocket::State;
+
+const REQUEST_TYPES: [&str; 4] = ["GetVersion", "GetSceneList", "GetInputList", "GetStats"];
+
+fn connect(address: SocketAddr) -> SharedWebsocket {
+    SharedWebsocket::connect(&format!("ws://{address}"), None, 1, OBSEventSubscription::All).unwrap()
+}
+
+#[test]
+fn requests_from_several_threads_are_matched_by_request_id() {
+    let (address, server) = common::serve(|stream| {
+        let requests: Vec<_> = REQUEST_TYPES.iter().map(|_| common::read_json(stream)).collect();
+        for request in requests.iter().rev() {
+            common::respond(stream, request);
+        }
+        common::close(stream)
+    });
+    let obs = connect(address);
+
+    let threads: Vec<_> = REQUEST_TYPES.iter().map(|request_type| {
+        let obs = obs.clone();
+        thread::spawn(move || obs.request(request_type, json!({})).map(|response| response.response_data()["requestType"] == *request_type))
+    }).collect();
+    for thread in threads {
+        assert!(thread.join().unwrap().unwrap());
+    }
+
+    obs.close(NORMAL_CLOSURE, "").unwrap();
+    assert_eq!(server.join().unwrap(), Some(NORMAL_CLOSURE));
+    let error = obs.request("GetVersion", json!({})).err().unwrap();
+    assert!(matches!(error, Error::ConnectionClosed(_)), "{error}");
+}
+
+#[test]
+fn request_times_out_and_its_late_response_is_dropped() {
+    let (address, server) = common::serve(|stream| {
+        let late = common::read_json(stream);
+        let next = common::read_json(stream);
+        common::respond(stream, &late);
+        common::respond(stream, &next);
+        common::close(stream)
+    });
+    let mut obs = connect(address);
+
+    obs.set_request_timeout(Duration::from_millis(200));
+    let error = obs.request("GetVersion", json!({})).err().unwrap();
+    assert!(matches!(error, Error::Websocket{kind: WebsocketError::RequestTimeout, ..}), "{error}");
+
+    obs.set_request_timeout(Duration::from_secs(5));
+    assert_eq!(obs.request("GetSceneList", json!({})).unwrap().response_data()["requestType"], "GetSceneList");
+    drop(obs);
+    assert_eq!(server.join().unwrap(), Some(NORMAL_CLOSURE));
+}
+
+#[test]
+fn requests_in_flight_fail_when_the_server_closes() {
+    let (address, server) = common::serve(|stream| {
+        common::read_json(stream);
+        common::read_json(stream);
+        let mut payload = 4011u16.to_be_bytes().to_vec();
+        payload.extend_from_slice(b"kicked");
+        common::send_frame(stream, 0x88, &payload);
+        common::read_frame(stream)
+    });
+    let obs = connect(address);
+    let events = obs.events();
+
+    let other = obs.clone();
+    let version = thread::spawn(move || other.request("GetVersion", json!({})));
+    let scenes = obs.request("GetSceneList", json!({}));
+    for result in [version.join().unwrap(), scenes] {
+        let error = result.err().unwrap();
+        assert_eq!(error.close_code(), Some(OBSWebSocketCloseCode::SessionInvalidated), "{error}");
+    }
+
+    assert!(events.recv().is_err());
+    assert_eq!(obs.state(), State::Closed);
+    assert_eq!(obs.close_frame().unwrap().reason(), "kicked");
+    let (opcode, payload) = server.join().unwrap();
+    assert_eq!((opcode, &payload[..2]), (0x08, &4011u16.to_be_bytes()[..]));
+}
+
+#[test]
+fn full_event_receiver_drops_new_events() {
+    let (address, server) = common::serve(|stream| {
+        let request = common::read_json(stream);
+        for index in 0..1030 {
+            common::send_event(stream, &index.to_string());
+        }
+        common::respond(stream, &request);
+        common::close(stream)
+    });
+    let obs = connect(address);
+    let events = obs.events();
+
+    //the response is read after every event was handed out
+    obs.request("GetVersion", json!({})).unwrap();
+    let scenes: Vec<String> = events.try_iter().map(|event| match event {
+        Event::CurrentProgramSceneChanged{scene_name, ..} => scene_name,
+        event => panic!("unexpected event {}", event.event_type())
+    }).collect();
+    assert_eq!(scenes.len(), 1024);
+    assert_eq!((scenes[0].as_str(), scenes[1023].as_str()), ("0", "1023"));
+    assert_eq!(events.try_recv(), Err(TryRecvError::Empty));
+
+    drop(obs);
+    assert_eq!(server.join().unwrap(), Some(NORMAL_CLOSURE));
+}
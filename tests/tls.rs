@@ -0,0 +1,119 @@
+#![cfg(feature = "tls")]
+//wss:// connections against a loopback server with a self-signed certificate
+
+use std::{io::{Read, Write}, net::TcpListener, sync::Arc, thread::{self, JoinHandle}};
+
+use base64::{Engine as _, engine::general_purpose};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use sha1::{Digest, Sha1};
+
+use obs_remote_1lt::error::{Error, WebsocketError};
+use obs_remote_1lt::obs::OBSEventSubscription;
+use obs_remote_1lt::tls::{certificate_sha256, TlsConfig};
+use obs_remote_1lt::url::WebsocketUrl;
+use obs_remote_1lt::websocket::Websocket;
+
+struct Server {
+    port: u16,
+    //SNI sent by the client, None if the handshake failed
+    handle: JoinHandle<Option<Option<String>>>
+}
+
+fn self_signed() -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let key = PrivatePkcs8KeyDer::from(certified.signing_key.serialize_der());
+    (certified.cert.der().clone(), key.into())
+}
+
+//accepts one connection, upgrades it and runs Hello/Identify/Identified without authentication
+fn serve(certificate: CertificateDer<'static>, key: PrivateKeyDer<'static>) -> Server {
+    let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions().unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![certificate], key).unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let handle = thread::spawn(move || {
+        let (tcp, _) = listener.accept().unwrap();
+        let mut stream = StreamOwned::new(ServerConnection::new(Arc::new(config)).unwrap(), tcp);
+
+        let mut request = Vec::new();
+        let mut byte = [0u8; 1];
+        while !request.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).ok()?;
+            request.push(byte[0]);
+        }
+        let sni = stream.conn.server_name().map(str::to_string);
+
+        let request = String::from_utf8(request).unwrap();
+        let key = request.lines().find_map(|line| line.strip_prefix("Sec-WebSocket-Key: ")).unwrap();
+        let accept = general_purpose::STANDARD.encode(Sha1::digest(format!("{key}258EAFA5-E914-47DA-95CA-C5AB0DC85B11")));
+        let response = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {accept}\r\nSec-WebSocket-Protocol: obswebsocket.json\r\n\r\n");
+        stream.write_all(response.as_bytes()).unwrap();
+
+        send_text(&mut stream, r#"{"op":0,"d":{"obsWebSocketVersion":"5.1.0","rpcVersion":1}}"#);
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).unwrap();
+        let mut masked = vec![0u8; 4 + (header[1] & 0x7f) as usize];
+        stream.read_exact(&mut masked).unwrap();
+        send_text(&mut stream, r#"{"op":2,"d":{"negotiatedRpcVersion":1}}"#);
+        Some(sni)
+    });
+    Server{port, handle}
+}
+
+fn send_text(stream: &mut impl Write, text: &str) {
+    let mut frame = vec![0x81, text.len() as u8];
+    frame.extend_from_slice(text.as_bytes());
+    stream.write_all(&frame).unwrap();
+}
+
+fn connect(url: &str, tls: &TlsConfig) -> Result<Websocket, Error> {
+    Websocket::connect_tls(WebsocketUrl::parse(url).unwrap(), tls, None, 1, OBSEventSubscription::None)
+}
+
+#[test]
+fn trusted_self_signed_certificate() {
+    let (certificate, key) = self_signed();
+    let server = serve(certificate.clone(), key);
+
+    let tls = TlsConfig::new().without_webpki_roots().add_root_certificate_der(certificate.to_vec());
+    let websocket = connect(&format!("wss://localhost:{}", server.port), &tls).unwrap();
+    assert!(websocket.url().is_secure());
+    assert_eq!(server.handle.join().unwrap(), Some(Some("localhost".to_string())));
+}
+
+#[test]
+fn server_name_for_ip_address() {
+    let (certificate, key) = self_signed();
+    let server = serve(certificate.clone(), key);
+
+    let tls = TlsConfig::new().without_webpki_roots().add_root_certificate_der(certificate.to_vec()).server_name("localhost");
+    connect(&format!("wss://127.0.0.1:{}", server.port), &tls).unwrap();
+    assert_eq!(server.handle.join().unwrap(), Some(Some("localhost".to_string())));
+}
+
+#[test]
+fn pinned_certificate_without_roots() {
+    let (certificate, key) = self_signed();
+    let server = serve(certificate.clone(), key);
+
+    let tls = TlsConfig::new().without_webpki_roots().without_sni().pin_sha256(certificate_sha256(&certificate));
+    connect(&format!("wss://localhost:{}", server.port), &tls).unwrap();
+    assert_eq!(server.handle.join().unwrap(), Some(None));
+}
+
+#[test]
+fn untrusted_certificate_fails_the_handshake() {
+    let (certificate, key) = self_signed();
+    let (other, _) = self_signed();
+
+    for tls in [TlsConfig::new(), TlsConfig::new().pin_sha256(certificate_sha256(&other))] {
+        let server = serve(certificate.clone(), key.clone_key());
+        let error = connect(&format!("wss://localhost:{}", server.port), &tls).err().unwrap();
+        assert!(matches!(error, Error::Io{kind: WebsocketError::TlsHandshakeError, ..}), "{error}");
+        assert_eq!(server.handle.join().unwrap(), None);
+    }
+}
@@ -0,0 +1,266 @@
+//Websocket against a loopback server that answers requests out of order and sends frames the client has to handle or reject
+
+mod common;
+
+use std::{io::Write, net::SocketAddr, thread, time::{Duration, Instant}};
+
+use serde_json::json;
+
+use obs_remote_1lt::close_frame::{CloseFrame, MESSAGE_TOO_BIG, NORMAL_CLOSURE, PROTOCOL_ERROR};
+use obs_remote_1lt::error::{Error, WebsocketError};
+use obs_remote_1lt::event::Event;
+use obs_remote_1lt::obs::{OBSEventSubscription, OBSWebSocketCloseCode};
+use obs_remote_1lt::websocket::{State, Websocket};
+
+fn connect(address: SocketAddr) -> Websocket {
+    Websocket::connect(&format!("ws://{address}"), None, 1, OBSEventSubscription::None).unwrap()
+}
+
+//sends the frames and returns the status code of the close frame the client fails the connection with
+fn reject(frames: &'static [(u8, &'static [u8])]) -> (Error, Option<u16>) {
+    let (address, server) = common::serve(move |stream| {
+        for (first, payload) in frames {
+            common::send_frame(stream, *first, payload);
+        }
+        common::close(stream)
+    });
+    let mut obs = connect(address);
+
+    let error = obs.next_event_timeout(Duration::from_secs(5)).err().unwrap();
+    assert_eq!(obs.state(), State::Closed);
+    (error, server.join().unwrap())
+}
+
+#[test]
+fn responses_to_other_requests_are_buffered() {
+    let (address, server) = common::serve(|stream| {
+        let first = common::read_json(stream);
+        let second = common::read_json(stream);
+        common::send_event(stream, "Live");
+        common::respond(stream, &second);
+        common::respond(stream, &first);
+        common::close(stream)
+    });
+    let mut obs = connect(address);
+
+    let version = obs.send_request("GetVersion", json!({})).unwrap();
+    let scenes = obs.send_request("GetSceneList", json!({})).unwrap();
+    assert_ne!(version, scenes);
+    //the response to GetSceneList arrives first and is kept for its own wait_for_response
+    assert_eq!(obs.wait_for_response(&version).unwrap().response_data()["requestType"], "GetVersion");
+    assert!(obs.has_buffered_events());
+    assert_eq!(obs.wait_for_response(&scenes).unwrap().response_data()["requestType"], "GetSceneList");
+
+    let event = obs.next_event_timeout(Duration::ZERO).unwrap().unwrap();
+    assert_eq!(event.event_type(), "CurrentProgramSceneChanged");
+    drop(obs);
+    assert_eq!(server.join().unwrap(), Some(NORMAL_CLOSURE));
+}
+
+#[test]
+fn request_times_out_and_its_late_response_is_dropped() {
+    let (address, server) = common::serve(|stream| {
+        let late = common::read_json(stream);
+        let next = common::read_json(stream);
+        common::respond(stream, &late);
+        common::respond(stream, &next);
+        common::close(stream)
+    });
+    let mut obs = connect(address);
+    obs.set_request_timeout(Duration::from_millis(200));
+
+    let started = Instant::now();
+    let error = obs.request("GetVersion", json!({})).err().unwrap();
+    assert!(matches!(error, Error::Websocket{kind: WebsocketError::RequestTimeout, ..}), "{error}");
+    assert!(started.elapsed() >= Duration::from_millis(200));
+
+    obs.set_request_timeout(Duration::from_secs(5));
+    assert_eq!(obs.request("GetSceneList", json!({})).unwrap().response_data()["requestType"], "GetSceneList");
+    drop(obs);
+    assert_eq!(server.join().unwrap(), Some(NORMAL_CLOSURE));
+}
+
+#[test]
+fn events_recieved_during_a_request_are_returned_in_order() {
+    let (address, server) = common::serve(|stream| {
+        let request = common::read_json(stream);
+        for scene in ["Intro", "Live"] {
+            common::send_event(stream, scene);
+        }
+        common::respond(stream, &request);
+        common::close(stream)
+    });
+    let mut obs = connect(address);
+
+    obs.request("GetVersion", json!({})).unwrap();
+    for scene in ["Intro", "Live"] {
+        match obs.next_event().unwrap() {
+            Event::CurrentProgramSceneChanged{scene_name, ..} => assert_eq!(scene_name, scene),
+            event => panic!("unexpected event {}", event.event_type())
+        }
+    }
+    let started = Instant::now();
+    assert!(obs.next_event_timeout(Duration::from_millis(200)).unwrap().is_none());
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    drop(obs);
+    assert_eq!(server.join().unwrap(), Some(NORMAL_CLOSURE));
+}
+
+#[test]
+fn reidentify_waits_for_identified() {
+    let (address, server) = common::serve(|stream| {
+        let reidentify = common::read_json(stream);
+        common::send_event(stream, "Live");
+        common::send_text(stream, &json!({"op": 2, "d": {"negotiatedRpcVersion": 1}}));
+        (reidentify, common::close(stream))
+    });
+    let mut obs = connect(address);
+
+    let subscriptions = OBSEventSubscription::Scenes | OBSEventSubscription::InputVolumeMeters;
+    obs.reidentify(subscriptions).unwrap();
+    assert_eq!(obs.event_subscriptions(), subscriptions);
+    assert!(obs.has_buffered_events());
+    drop(obs);
+    let (reidentify, code) = server.join().unwrap();
+    assert_eq!(reidentify, json!({"op": 3, "d": {"eventSubscriptions": (1 << 2) | (1 << 16)}}));
+    assert_eq!(code, Some(NORMAL_CLOSURE));
+}
+
+#[test]
+fn fragmented_message_with_ping_between_fragments() {
+    let (address, server) = common::serve(|stream| {
+        let request = common::read_json(stream);
+        let response = json!({"op": 7, "d": {
+            "requestType": "GetVersion",
+            "requestId": request["d"]["requestId"],
+            "requestStatus": {"result": true, "code": 100},
+            "responseData": {"obsVersion": "30.2.0"}
+        }}).to_string();
+        let (first, rest) = response.as_bytes().split_at(10);
+        let (middle, last) = rest.split_at(20);
+
+        common::send_frame(stream, 0x01, first);
+        common::send_frame(stream, 0x89, b"between");
+        common::send_frame(stream, 0x00, middle);
+        common::send_frame(stream, 0x80, last);
+        let pong = common::read_frame(stream);
+        (pong, common::close(stream))
+    });
+    let mut obs = connect(address);
+
+    let response = obs.request("GetVersion", json!({})).unwrap();
+    assert_eq!(response.response_data()["obsVersion"], "30.2.0");
+    drop(obs);
+    assert_eq!(server.join().unwrap(), ((0x0a, b"between".to_vec()), Some(NORMAL_CLOSURE)));
+}
+
+#[test]
+fn ping_is_answered_with_its_payload() {
+    let (address, server) = common::serve(|stream| {
+        common::send_frame(stream, 0x89, b"heartbeat 42");
+        let pong = common::read_frame(stream);
+        common::send_text(stream, &json!({"op": 5, "d": {"eventType": "ExitStarted", "eventIntent": 1}}));
+        (pong, common::close(stream))
+    });
+    let mut obs = connect(address);
+
+    assert!(obs.next_event_timeout(Duration::from_secs(5)).unwrap().is_some());
+    drop(obs);
+    assert_eq!(server.join().unwrap(), ((0x0a, b"heartbeat 42".to_vec()), Some(NORMAL_CLOSURE)));
+}
+
+#[test]
+fn oversized_control_frame_fails_with_1002() {
+    let (error, code) = reject(&[(0x89, &[b'x'; 126])]);
+    assert!(matches!(error, Error::Websocket{kind: WebsocketError::ControlFrameError, ..}), "{error}");
+    assert_eq!(code, Some(PROTOCOL_ERROR));
+}
+
+#[test]
+fn fragmented_control_frame_fails_with_1002() {
+    let (error, code) = reject(&[(0x09, b"first"), (0x80, b"last")]);
+    assert!(matches!(error, Error::Websocket{kind: WebsocketError::ControlFrameError, ..}), "{error}");
+    assert_eq!(code, Some(PROTOCOL_ERROR));
+}
+
+#[test]
+fn continuation_without_a_first_frame_fails_with_1002() {
+    let (error, code) = reject(&[(0x80, b"{}")]);
+    assert!(matches!(error, Error::Websocket{kind: WebsocketError::FragmentationError, ..}), "{error}");
+    assert_eq!(code, Some(PROTOCOL_ERROR));
+}
+
+#[test]
+fn new_message_before_the_fragmented_one_ended_fails_with_1002() {
+    let (error, code) = reject(&[(0x01, b"{\"op\":"), (0x81, b"{}")]);
+    assert!(matches!(error, Error::Websocket{kind: WebsocketError::FragmentationError, ..}), "{error}");
+    assert_eq!(code, Some(PROTOCOL_ERROR));
+}
+
+#[test]
+fn oversized_frame_fails_with_1009_before_it_is_read() {
+    let (address, server) = common::serve(|stream| {
+        //header of a 1 TiB frame without its payload
+        stream.write_all(&[0x81, 127, 0, 0, 1, 0, 0, 0, 0, 0]).unwrap();
+        common::close(stream)
+    });
+    let mut obs = connect(address);
+
+    let error = obs.next_event_timeout(Duration::from_secs(5)).err().unwrap();
+    assert!(matches!(error, Error::Websocket{kind: WebsocketError::MessageTooBig, ..}), "{error}");
+    assert_eq!(server.join().unwrap(), Some(MESSAGE_TOO_BIG));
+}
+
+#[test]
+fn close_frame_of_the_server_is_parsed_and_echoed() {
+    let (address, server) = common::serve(|stream| {
+        let mut payload = 4009u16.to_be_bytes().to_vec();
+        payload.extend_from_slice("Authentication failed, prüfen".as_bytes());
+        common::send_frame(stream, 0x88, &payload);
+        common::read_frame(stream)
+    });
+    let mut obs = connect(address);
+
+    let error = obs.next_event_timeout(Duration::from_secs(5)).err().unwrap();
+    assert_eq!(error.close_code(), Some(OBSWebSocketCloseCode::AuthenticationFailed));
+    assert_eq!(obs.close_frame(), Some(&CloseFrame::new(4009, "Authentication failed, prüfen")));
+    assert_eq!(obs.state(), State::Closed);
+    let (opcode, payload) = server.join().unwrap();
+    assert_eq!((opcode, &payload[..2]), (0x08, &4009u16.to_be_bytes()[..]));
+}
+
+#[test]
+fn invalid_close_payload_fails_with_1002() {
+    //a single byte, the reserved status code 1005 and a reason that is not UTF-8
+    for payload in [vec![0x03], vec![0x03, 0xed], vec![0x03, 0xe8, 0xff]] {
+        let (address, server) = common::serve(move |stream| {
+            common::send_frame(stream, 0x88, &payload);
+            common::close(stream)
+        });
+        let mut obs = connect(address);
+
+        let error = obs.next_event_timeout(Duration::from_secs(5)).err().unwrap();
+        assert!(matches!(error, Error::Websocket{kind: WebsocketError::ControlFrameError, ..}), "{error}");
+        assert_eq!(server.join().unwrap(), Some(PROTOCOL_ERROR));
+    }
+}
+
+#[test]
+fn close_gives_up_on_a_server_that_does_not_answer() {
+    let (address, server) = common::serve(|stream| {
+        let (opcode, _) = common::read_frame(stream);
+        //keeps the connection busy without answering the close frame until the client shuts it down
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(20) && stream.write_all(&[0x89, 0]).is_ok() {
+            thread::sleep(Duration::from_millis(100));
+        }
+        opcode
+    });
+    let mut obs = connect(address);
+
+    let started = Instant::now();
+    obs.close(NORMAL_CLOSURE, "").unwrap();
+    assert!(started.elapsed() < Duration::from_secs(8), "{:?}", started.elapsed());
+    assert_eq!(obs.state(), State::Closed);
+    assert_eq!(server.join().unwrap(), 0x08);
+}